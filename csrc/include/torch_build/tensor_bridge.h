@@ -0,0 +1,34 @@
+// Declarations to pass at::Tensor across a cxx bridge.
+//
+// Declare the tensor as an opaque C++ type in the bridge module and
+// use UniquePtr<Tensor> or SharedPtr<Tensor> in signatures.
+//
+//     #[cxx::bridge]
+//     mod ffi {
+//         #[namespace = "at"]
+//         unsafe extern "C++" {
+//             include!("torch_build/tensor_bridge.h");
+//             type Tensor;
+//         }
+//     }
+#pragma once
+
+#include <memory>
+
+#include <ATen/core/Tensor.h>
+
+namespace torch_build {
+
+// Move a tensor to the heap so that it can be returned as
+// UniquePtr<Tensor>.
+inline std::unique_ptr<at::Tensor> into_unique(at::Tensor tensor) {
+  return std::make_unique<at::Tensor>(std::move(tensor));
+}
+
+// Move a tensor to the heap so that it can be returned as
+// SharedPtr<Tensor>.
+inline std::shared_ptr<at::Tensor> into_shared(at::Tensor tensor) {
+  return std::make_shared<at::Tensor>(std::move(tensor));
+}
+
+}  // namespace torch_build
@@ -9,6 +9,7 @@
 #include<vector>
 #include "torch_api.h"
 
+#ifndef TCH_NO_IMAGE
 #define STB_IMAGE_IMPLEMENTATION
 #include "stb_image.h"
 
@@ -17,6 +18,7 @@
 
 #define STB_IMAGE_RESIZE_IMPLEMENTATION
 #include "stb_image_resize.h"
+#endif
 
 using namespace std;
 
@@ -361,6 +363,7 @@ tensor at_load(char *filename) {
   return nullptr;
 }
 
+#ifndef TCH_NO_IMAGE
 tensor at_load_image(char *filename) {
   PROTECT(
     int w = -1;
@@ -425,6 +428,8 @@ int at_save_image(tensor tensor, char *filename) {
   return -1;
 }
 
+#endif
+
 int at_get_num_interop_threads() {
   PROTECT(return at::get_num_interop_threads();)
   return -1;
@@ -464,6 +469,7 @@ void at_set_qengine(int qengine_id) {
   )
 }
 
+#ifndef TCH_NO_IMAGE
 tensor at_resize_image(tensor tensor, int out_w, int out_h) {
   PROTECT(
     auto sizes = tensor->sizes();
@@ -483,6 +489,8 @@ tensor at_resize_image(tensor tensor, int out_w, int out_h) {
   return nullptr;
 }
 
+#endif
+
 void at_free(tensor t) {
   delete(t);
 }
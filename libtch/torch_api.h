@@ -70,10 +70,12 @@ void at_print(tensor);
 char *at_to_string(tensor, int line_size);
 void at_save(tensor, char *filename);
 tensor at_load(char *filename);
+#ifndef TCH_NO_IMAGE
 tensor at_load_image(char *filename);
 tensor at_load_image_from_memory(unsigned char *img_data, size_t img_size);
 int at_save_image(tensor, char *filename);
 tensor at_resize_image(tensor, int w, int h);
+#endif
 
 void at_save_multi(tensor *tensors, char **tensor_names, int ntensors, char *filename);
 /* [at_load_multi] takes as input an array of nullptr for [tensors]. */
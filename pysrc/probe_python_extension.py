@@ -0,0 +1,11 @@
+import sysconfig
+
+import torch
+
+print('PYTHON_EXT_SUFFIX:', sysconfig.get_config_var('EXT_SUFFIX'))
+print('PYTHON_INCLUDE:', sysconfig.get_paths()['include'])
+
+for name in ['COMPILER_TYPE', 'STDLIB', 'BUILD_ABI']:
+    value = getattr(torch._C, f'_PYBIND11_{name}', None)
+    if value is not None:
+        print(f'PYBIND11_{name}:', value)
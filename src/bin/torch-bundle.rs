@@ -0,0 +1,78 @@
+//! Copy shared libraries required by libtorch into a deployable directory.
+
+#[cfg(target_os = "linux")]
+fn main() -> anyhow::Result<()> {
+    use anyhow::{anyhow, bail};
+    use std::env;
+
+    const USAGE: &str = "\
+Usage: torch-bundle [OPTIONS] <DEST_DIR>
+
+Options:
+  --cuda             Bundle CUDA libraries
+  --no-cuda          Do not bundle CUDA libraries
+  --python           Bundle Python libraries
+  --libstdcxx        Bundle libstdc++
+  --patch-runpath    Set RUNPATH of bundled libraries to $ORIGIN
+  --allow-missing    Skip libraries that cannot be found
+  --artifact <PATH>  Bundle dependencies of an executable or library
+  -h, --help         Print help";
+
+    let mut bundle = torch_build::Bundle::new();
+    let mut dest_dir = None;
+    let mut args = env::args_os().skip(1);
+
+    while let Some(arg) = args.next() {
+        match arg.to_str() {
+            Some("--cuda") => {
+                bundle.use_cuda_api(true);
+            }
+            Some("--no-cuda") => {
+                bundle.use_cuda_api(false);
+            }
+            Some("--python") => {
+                bundle.link_python(true);
+            }
+            Some("--libstdcxx") => {
+                bundle.bundle_libstdcxx(true);
+            }
+            Some("--patch-runpath") => {
+                bundle.patch_runpath(true);
+            }
+            Some("--allow-missing") => {
+                bundle.allow_missing(true);
+            }
+            Some("--artifact") => {
+                let path = args
+                    .next()
+                    .ok_or_else(|| anyhow!("--artifact expects a path\n\n{USAGE}"))?;
+                bundle.artifact(path);
+            }
+            Some("-h" | "--help") => {
+                println!("{USAGE}");
+                return Ok(());
+            }
+            Some(flag) if flag.starts_with('-') => {
+                bail!("unknown option {flag}\n\n{USAGE}");
+            }
+            _ => {
+                if dest_dir.replace(arg).is_some() {
+                    bail!("only one destination directory is allowed\n\n{USAGE}");
+                }
+            }
+        }
+    }
+
+    let dest_dir = dest_dir.ok_or_else(|| anyhow!("missing destination directory\n\n{USAGE}"))?;
+
+    for file in bundle.bundle(dest_dir)? {
+        println!("{}", file.display());
+    }
+
+    Ok(())
+}
+
+#[cfg(not(target_os = "linux"))]
+fn main() -> anyhow::Result<()> {
+    anyhow::bail!("torch-bundle only supports Linux")
+}
@@ -1,7 +1,35 @@
+#[cfg(feature = "bindgen")]
+pub use bindings::BindgenOptions;
+#[cfg(feature = "bindgen")]
+mod bindings;
+
+pub use cache::CompilerCache;
+mod cache;
+
 pub use cpp::*;
 mod cpp;
 
 pub use cuda::*;
 mod cuda;
 
+pub use hip::*;
+mod hip;
+
+pub use incremental::compile_incremental;
+mod incremental;
+
+mod pch;
+
+pub use python::*;
+mod python;
+
+pub use rpath::*;
+mod rpath;
+
+pub use tch::*;
+mod tch;
+
+pub use torch::*;
+mod torch;
+
 pub(crate) mod utils;
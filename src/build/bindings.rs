@@ -0,0 +1,244 @@
+/// Options of the binding generation stage of extension builders,
+/// for example [CudaExtension::bindgen()](crate::CudaExtension::bindgen).
+///
+/// Without allowlists, bindings are generated for every item in the
+/// registered headers and the headers they include.
+#[derive(Debug, Clone, Default)]
+pub struct BindgenOptions {
+    output: Option<String>,
+    allowlist_functions: Vec<String>,
+    allowlist_types: Vec<String>,
+    allowlist_vars: Vec<String>,
+    type_mappings: Vec<(String, String)>,
+    tch_preset: bool,
+}
+
+/// The libtch handle typedefs in `torch_api.h` and the torch-sys types
+/// behind the raw pointers of tch.
+const TCH_HANDLE_TYPES: &[(&str, &str)] = &[
+    ("tensor", "C_tensor"),
+    ("scalar", "C_scalar"),
+    ("optimizer", "C_optimizer"),
+    ("module", "CModule_"),
+    ("ivalue", "CIValue"),
+];
+
+/// The namespaces of libtorch internals.
+const TORCH_NAMESPACES: &str = "(at|c10|caffe2|torch)";
+
+impl BindgenOptions {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Set the file name of the generated bindings in the output
+    /// directory. It defaults to `{name}.rs`, where `name` is the
+    /// library name given to the builder.
+    pub fn output<S>(&mut self, file_name: S) -> &mut Self
+    where
+        S: AsRef<str>,
+    {
+        self.output = Some(file_name.as_ref().to_owned());
+        self
+    }
+
+    pub fn allowlist_function<S>(&mut self, pattern: S) -> &mut Self
+    where
+        S: AsRef<str>,
+    {
+        self.allowlist_functions.push(pattern.as_ref().to_owned());
+        self
+    }
+
+    pub fn allowlist_functions<S>(&mut self, patterns: S) -> &mut Self
+    where
+        S: IntoIterator,
+        S::Item: AsRef<str>,
+    {
+        self.allowlist_functions
+            .extend(patterns.into_iter().map(|p| p.as_ref().to_owned()));
+        self
+    }
+
+    pub fn allowlist_type<S>(&mut self, pattern: S) -> &mut Self
+    where
+        S: AsRef<str>,
+    {
+        self.allowlist_types.push(pattern.as_ref().to_owned());
+        self
+    }
+
+    pub fn allowlist_types<S>(&mut self, patterns: S) -> &mut Self
+    where
+        S: IntoIterator,
+        S::Item: AsRef<str>,
+    {
+        self.allowlist_types
+            .extend(patterns.into_iter().map(|p| p.as_ref().to_owned()));
+        self
+    }
+
+    pub fn allowlist_var<S>(&mut self, pattern: S) -> &mut Self
+    where
+        S: AsRef<str>,
+    {
+        self.allowlist_vars.push(pattern.as_ref().to_owned());
+        self
+    }
+
+    pub fn allowlist_vars<S>(&mut self, patterns: S) -> &mut Self
+    where
+        S: IntoIterator,
+        S::Item: AsRef<str>,
+    {
+        self.allowlist_vars
+            .extend(patterns.into_iter().map(|p| p.as_ref().to_owned()));
+        self
+    }
+
+    /// Bind the C type `c_name`, usually a typedef, to the Rust type
+    /// `rust_type` instead of generating a binding for it.
+    pub fn map_type<S, T>(&mut self, c_name: S, rust_type: T) -> &mut Self
+    where
+        S: AsRef<str>,
+        T: AsRef<str>,
+    {
+        self.type_mappings
+            .push((c_name.as_ref().to_owned(), rust_type.as_ref().to_owned()));
+        self
+    }
+
+    /// Use the preset for headers taking libtch handles.
+    ///
+    /// The `tensor`, `scalar`, `optimizer`, `module` and `ivalue`
+    /// typedefs of `torch_api.h` are mapped onto pointers to
+    /// `torch_sys::C_tensor` and the other torch-sys types, so that
+    /// the raw pointers of tch, for example `Tensor::as_mut_ptr()`, can
+    /// be passed without casts. The crate using the bindings must
+    /// depend on torch-sys. Functions of libtorch namespaces are not
+    /// bound and `std` and libtorch types are made opaque.
+    pub fn tch_preset(&mut self, enabled: bool) -> &mut Self {
+        self.tch_preset = enabled;
+        self
+    }
+
+    /// The file name of the generated bindings for the library
+    /// `name`.
+    pub(crate) fn output_file(&self, name: &str) -> String {
+        match &self.output {
+            Some(file_name) => file_name.clone(),
+            None => format!("{name}.rs"),
+        }
+    }
+
+    /// Apply the options to the [bindgen::Builder]. The
+    /// `configure_bindgen()` methods of
+    /// [CppExtension](crate::CppExtension) and
+    /// [CudaExtension](crate::CudaExtension) call it with the options
+    /// given to their `bindgen()` methods.
+    pub fn configure_bindgen(&self, builder: bindgen::Builder) -> bindgen::Builder {
+        let builder = if self.tch_preset {
+            let builder = TCH_HANDLE_TYPES
+                .iter()
+                .fold(builder, |builder, (c_name, sys_name)| {
+                    map_type(builder, c_name, &format!("*mut torch_sys::{sys_name}"))
+                });
+            builder
+                .blocklist_function(format!("{TORCH_NAMESPACES}::.*"))
+                .blocklist_item(".*::(detail|impl|internal)::.*")
+                .opaque_type("std::.*")
+                .opaque_type(format!("{TORCH_NAMESPACES}::.*"))
+        } else {
+            builder
+        };
+
+        let builder = self
+            .type_mappings
+            .iter()
+            .fold(builder, |builder, (c_name, rust_type)| {
+                map_type(builder, c_name, rust_type)
+            });
+
+        let builder = self
+            .allowlist_functions
+            .iter()
+            .fold(builder, |builder, pattern| {
+                builder.allowlist_function(pattern)
+            });
+        let builder = self
+            .allowlist_types
+            .iter()
+            .fold(builder, |builder, pattern| builder.allowlist_type(pattern));
+        self.allowlist_vars
+            .iter()
+            .fold(builder, |builder, pattern| builder.allowlist_var(pattern))
+    }
+}
+
+/// Blocklist the C type and define it as an alias of the Rust type.
+fn map_type(builder: bindgen::Builder, c_name: &str, rust_type: &str) -> bindgen::Builder {
+    builder
+        .blocklist_type(c_name)
+        .raw_line(format!("pub type {c_name} = {rust_type};"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{BindgenOptions, TCH_HANDLE_TYPES};
+    use std::{fs, panic};
+
+    #[test]
+    fn tch_preset_maps_handle_types() {
+        let mut options = BindgenOptions::new();
+        options.tch_preset(true);
+        let flags = options
+            .configure_bindgen(bindgen::Builder::default())
+            .command_line_flags();
+
+        for (c_name, sys_name) in TCH_HANDLE_TYPES {
+            let line = format!("pub type {c_name} = *mut torch_sys::{sys_name};");
+            assert!(flags.contains(&line), "missing {line:?} in {flags:?}");
+            assert!(flags.contains(&c_name.to_string()));
+        }
+    }
+
+    #[test]
+    fn tch_preset_bindings() {
+        // libclang is loaded at runtime and may be missing.
+        if panic::catch_unwind(bindgen::clang_version).is_err() {
+            eprintln!("libclang is not found, skipped");
+            return;
+        }
+
+        let dir = std::env::temp_dir().join(format!("tch-preset-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let header = dir.join("kernel.h");
+        fs::write(
+            &header,
+            "typedef void *tensor;\n\
+             typedef void *scalar;\n\
+             typedef void *optimizer;\n\
+             typedef void *module;\n\
+             typedef void *ivalue;\n\
+             void kernel(tensor out, tensor in, scalar s, optimizer o, module m, ivalue v);\n",
+        )
+        .unwrap();
+
+        let mut options = BindgenOptions::new();
+        options.tch_preset(true).allowlist_function("kernel");
+        let bindings = options
+            .configure_bindgen(bindgen::Builder::default().header(header.display().to_string()))
+            .generate()
+            .unwrap()
+            .to_string();
+
+        for (c_name, sys_name) in TCH_HANDLE_TYPES {
+            let line = format!("pub type {c_name} = *mut torch_sys::{sys_name};");
+            assert!(bindings.contains(&line), "missing {line:?} in\n{bindings}");
+        }
+        assert!(bindings.contains("pub fn kernel("));
+        assert!(bindings.contains("out: tensor"));
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
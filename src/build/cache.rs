@@ -0,0 +1,168 @@
+use anyhow::Result;
+use std::{
+    env,
+    ffi::{OsStr, OsString},
+    path::{Component, Path, PathBuf},
+    process::Command,
+};
+
+/// A compiler cache that wraps compiler invocations.
+///
+/// It applies to C++, HIP and nvcc invocations alike. Both ccache
+/// and sccache accept nvcc as the wrapped compiler.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum CompilerCache {
+    /// `ccache` in `PATH`.
+    Ccache,
+    /// `sccache` in `PATH`.
+    Sccache,
+    /// A ccache-compatible launcher at the given path.
+    Custom(PathBuf),
+}
+
+impl CompilerCache {
+    /// Create a [Custom](CompilerCache::Custom) cache.
+    pub fn custom<P>(program: P) -> Self
+    where
+        P: AsRef<Path>,
+    {
+        Self::Custom(program.as_ref().to_owned())
+    }
+
+    fn program(&self) -> &Path {
+        match self {
+            Self::Ccache => Path::new("ccache"),
+            Self::Sccache => Path::new("sccache"),
+            Self::Custom(path) => path,
+        }
+    }
+}
+
+/// The compiler cache configured on an extension builder.
+#[derive(Debug, Clone)]
+pub(crate) struct Launcher {
+    pub cache: CompilerCache,
+    /// Absolute paths under this directory are rewritten relative to
+    /// the working directory, so that cache entries can be shared
+    /// across checkouts in different locations.
+    pub base_dir: Option<PathBuf>,
+}
+
+impl Launcher {
+    /// Run the compiler command through the compiler cache.
+    pub fn wrap(&self, cmd: Command) -> Result<Command> {
+        let program = self.cache.program();
+        let cwd = match cmd.get_current_dir() {
+            Some(dir) => dir.to_owned(),
+            None => env::current_dir()?,
+        };
+        let base_dir = self
+            .base_dir
+            .as_ref()
+            .map(|dir| dir.canonicalize().unwrap_or_else(|_| cwd.join(dir)));
+
+        let normalize = |arg: &OsStr| -> OsString {
+            match &base_dir {
+                Some(base_dir) => normalize_arg(arg, base_dir, &cwd),
+                None => arg.to_owned(),
+            }
+        };
+
+        // The compiler may already be wrapped by cc, for example if
+        // RUSTC_WRAPPER is set to sccache.
+        let already_wrapped = Path::new(cmd.get_program()).file_stem() == program.file_stem();
+        let mut wrapped = if already_wrapped {
+            Command::new(cmd.get_program())
+        } else {
+            let mut wrapped = Command::new(program);
+            wrapped.arg(cmd.get_program());
+            wrapped
+        };
+        wrapped.args(cmd.get_args().map(normalize));
+
+        cmd.get_envs().for_each(|(key, val)| match val {
+            Some(val) => {
+                wrapped.env(key, val);
+            }
+            None => {
+                wrapped.env_remove(key);
+            }
+        });
+        if let Some(dir) = cmd.get_current_dir() {
+            wrapped.current_dir(dir);
+        }
+        if let (CompilerCache::Ccache, Some(base_dir)) = (&self.cache, &base_dir) {
+            wrapped.env("CCACHE_BASEDIR", base_dir);
+        }
+
+        Ok(wrapped)
+    }
+}
+
+/// Rewrite an absolute path in the argument, possibly after an
+/// option prefix like `-I`, relative to `cwd` if it is under
+/// `base_dir`.
+fn normalize_arg(arg: &OsStr, base_dir: &Path, cwd: &Path) -> OsString {
+    const PREFIXES: &[&str] = &["", "-I", "-L", "-iquote", "-isystem", "-ccbin="];
+
+    let Some(text) = arg.to_str() else {
+        return arg.to_owned();
+    };
+
+    PREFIXES
+        .iter()
+        .find_map(|prefix| {
+            let path = Path::new(text.strip_prefix(prefix)?);
+            (path.is_absolute() && path.starts_with(base_dir)).then(|| {
+                let mut arg = OsString::from(prefix);
+                arg.push(relative_path(path, cwd));
+                arg
+            })
+        })
+        .unwrap_or_else(|| arg.to_owned())
+}
+
+/// Compute the path of `path` relative to `dir`. Both must be
+/// absolute.
+fn relative_path(path: &Path, dir: &Path) -> PathBuf {
+    let path: Vec<Component> = path.components().collect();
+    let dir: Vec<Component> = dir.components().collect();
+    let common = path
+        .iter()
+        .zip(&dir)
+        .take_while(|(lhs, rhs)| lhs == rhs)
+        .count();
+
+    let relative: PathBuf = dir[common..]
+        .iter()
+        .map(|_| Component::ParentDir)
+        .chain(path[common..].iter().copied())
+        .collect();
+
+    if relative.as_os_str().is_empty() {
+        PathBuf::from(".")
+    } else {
+        relative
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::normalize_arg;
+    use std::{ffi::OsStr, path::Path};
+
+    #[test]
+    fn normalize_paths_under_base_dir() {
+        let base_dir = Path::new("/work");
+        let cwd = Path::new("/work/crates/ext");
+        let normalize = |arg: &str| normalize_arg(OsStr::new(arg), base_dir, cwd);
+
+        assert_eq!(normalize("/work/crates/ext/csrc/nms.cu"), "csrc/nms.cu");
+        assert_eq!(
+            normalize("-I/work/target/debug/build/ext/out/include"),
+            "-I../../target/debug/build/ext/out/include"
+        );
+        assert_eq!(normalize("-I/usr/include"), "-I/usr/include");
+        assert_eq!(normalize("-O2"), "-O2");
+    }
+}
@@ -1,8 +1,13 @@
-use crate::{probe_python, ProbePython};
-use anyhow::{ensure, Context as _, Result};
+use super::cache::Launcher;
+#[cfg(feature = "bindgen")]
+use crate::BindgenOptions;
+use crate::{probe_python, CompilerCache, LinkTarget, ProbePython, RpathPolicy};
+use anyhow::{anyhow, ensure, Context as _, Result};
 use cfg_if::cfg_if;
+use itertools::chain;
 use log::warn;
 use std::{
+    env,
     path::{Path, PathBuf},
     process::Command,
     str,
@@ -12,11 +17,23 @@ use std::{
 pub struct CppExtension {
     use_cuda_api: bool,
     link_python: bool,
+    force_cuda_link: bool,
+    rpath_policy: RpathPolicy,
+    extra_rpaths: Vec<String>,
+    rpath_targets: Vec<LinkTarget>,
+    compiler_cache: Option<CompilerCache>,
+    compiler_cache_base_dir: Option<PathBuf>,
+    compile_commands: bool,
+    compile_commands_dir: Option<PathBuf>,
+    precompiled_header: Option<String>,
+    #[cfg(feature = "bindgen")]
+    bindgen: Option<BindgenOptions>,
     includes: Vec<PathBuf>,
     link_searches: Vec<PathBuf>,
     libraries: Vec<String>,
     headers: Vec<PathBuf>,
     sources: Vec<PathBuf>,
+    out_dir: Option<PathBuf>,
 }
 
 impl CppExtension {
@@ -24,14 +41,37 @@ impl CppExtension {
         Self {
             use_cuda_api: false,
             link_python: false,
+            force_cuda_link: false,
+            rpath_policy: RpathPolicy::Absolute,
+            extra_rpaths: vec![],
+            rpath_targets: vec![],
+            compiler_cache: None,
+            compiler_cache_base_dir: None,
+            compile_commands: false,
+            compile_commands_dir: None,
+            precompiled_header: None,
+            #[cfg(feature = "bindgen")]
+            bindgen: None,
             includes: vec![],
             headers: vec![],
             sources: vec![],
             link_searches: vec![],
             libraries: vec![],
+            out_dir: None,
         }
     }
 
+    pub fn out_dir(&self) -> Result<PathBuf> {
+        Ok(match &self.out_dir {
+            Some(dir) => dir.clone(),
+            None => {
+                let out_dir = env::var_os("OUT_DIR")
+                    .ok_or_else(|| anyhow!("OUT_DIR environment variable is not set"))?;
+                PathBuf::from(out_dir)
+            }
+        })
+    }
+
     pub fn use_cuda_api(&mut self, enabled: bool) -> &mut Self {
         self.use_cuda_api = enabled;
         self
@@ -42,6 +82,127 @@ impl CppExtension {
         self
     }
 
+    /// Force linking to libtorch CUDA libraries if CUDA is available.
+    ///
+    /// It compiles the `dummy_cuda_dependency()` function that
+    /// references libtorch CUDA symbols and links CUDA libraries with
+    /// `-Wl,--no-as-needed`. The [link()](CppExtension::link) writes
+    /// `cuda_dependency.rs` to the output directory, which provides
+    /// `keep_cuda_dependency()` to be called on the Rust side.
+    pub fn force_cuda_link(&mut self, enabled: bool) -> &mut Self {
+        self.force_cuda_link = enabled;
+        self
+    }
+
+    /// Set the policy to embed rpath into linked artifacts. It
+    /// defaults to [RpathPolicy::Absolute].
+    pub fn rpath_policy(&mut self, policy: RpathPolicy) -> &mut Self {
+        self.rpath_policy = policy;
+        self
+    }
+
+    /// Add an rpath entry that is embedded verbatim in addition to
+    /// the entries generated by the
+    /// [rpath_policy()](CppExtension::rpath_policy).
+    pub fn extra_rpath<S>(&mut self, entry: S) -> &mut Self
+    where
+        S: AsRef<str>,
+    {
+        self.extra_rpaths.push(entry.as_ref().to_owned());
+        self
+    }
+
+    pub fn extra_rpaths<S>(&mut self, entries: S) -> &mut Self
+    where
+        S: IntoIterator,
+        S::Item: AsRef<str>,
+    {
+        self.extra_rpaths
+            .extend(entries.into_iter().map(|s| s.as_ref().to_owned()));
+        self
+    }
+
+    /// Add a kind of Cargo targets that rpath applies to. If no
+    /// target is added, rpath applies to all targets.
+    pub fn rpath_target(&mut self, target: LinkTarget) -> &mut Self {
+        self.rpath_targets.push(target);
+        self
+    }
+
+    pub fn rpath_targets<T>(&mut self, targets: T) -> &mut Self
+    where
+        T: IntoIterator<Item = LinkTarget>,
+    {
+        self.rpath_targets.extend(targets);
+        self
+    }
+
+    /// Run compilers through a compiler cache such as ccache or
+    /// sccache.
+    pub fn compiler_cache(&mut self, cache: CompilerCache) -> &mut Self {
+        self.compiler_cache = Some(cache);
+        self
+    }
+
+    /// Rewrite absolute paths under `dir` in compiler arguments
+    /// relative to the working directory, like `CCACHE_BASEDIR`, so
+    /// that cached results are reused across checkouts in different
+    /// locations. It applies only if
+    /// [compiler_cache()](CppExtension::compiler_cache) is set.
+    pub fn compiler_cache_base_dir<P>(&mut self, dir: P) -> &mut Self
+    where
+        P: AsRef<Path>,
+    {
+        self.compiler_cache_base_dir = Some(dir.as_ref().to_owned());
+        self
+    }
+
+    /// Write `compile_commands.json` with the compiler invocation of
+    /// each source to the output directory when
+    /// [build()](CppExtension::build) runs. Tools like clangd read the file
+    /// to find libtorch headers and defines.
+    pub fn compile_commands(&mut self, enabled: bool) -> &mut Self {
+        self.compile_commands = enabled;
+        self
+    }
+
+    /// Also write `compile_commands.json` to a stable directory, such
+    /// as the workspace root. It implies
+    /// [compile_commands(true)](CppExtension::compile_commands).
+    pub fn compile_commands_dir<P>(&mut self, dir: P) -> &mut Self
+    where
+        P: AsRef<Path>,
+    {
+        self.compile_commands = true;
+        self.compile_commands_dir = Some(dir.as_ref().to_owned());
+        self
+    }
+
+    /// Precompile a header, such as `torch/extension.h`, and include
+    /// it in every source when [build()](CppExtension::build) runs.
+    ///
+    /// The precompiled header is built once for each libtorch and
+    /// flag set and cached in the output directory. It requires gcc
+    /// or clang.
+    pub fn precompiled_header<S>(&mut self, header: S) -> &mut Self
+    where
+        S: AsRef<str>,
+    {
+        self.precompiled_header = Some(header.as_ref().to_owned());
+        self
+    }
+
+    /// Apply the [BindgenOptions], for example
+    /// [BindgenOptions::tch_preset()], in
+    /// [configure_bindgen()](CppExtension::configure_bindgen). Unlike
+    /// [CudaExtension::bindgen()](crate::CudaExtension::bindgen),
+    /// bindings are not generated by [build()](CppExtension::build).
+    #[cfg(feature = "bindgen")]
+    pub fn bindgen(&mut self, options: BindgenOptions) -> &mut Self {
+        self.bindgen = Some(options);
+        self
+    }
+
     pub fn include<P>(&mut self, path: P) -> &mut Self
     where
         P: AsRef<Path>,
@@ -136,9 +297,51 @@ impl CppExtension {
     /// [configure()](CppExtension::configure) and then
     /// [link()](CppExtension::link).
     pub fn build(&self, name: &str) -> Result<()> {
+        use super::{
+            incremental::{
+                archive_objects, compile_incremental_with, emit_compile_commands,
+                libtorch_fingerprint, print_cargo_rerun_if_changed_deps,
+            },
+            pch::configure_pch,
+            utils::print_cargo_rerun_if_changed,
+        };
+
+        let fingerprint = libtorch_fingerprint()?;
         let mut build = cc::Build::new();
         self.configure_cc(&mut build)?;
-        build.try_compile(name)?;
+        if let Some(header) = &self.precompiled_header {
+            configure_pch(
+                &mut build,
+                &self.out_dir()?,
+                header,
+                &fingerprint,
+                self.launcher().as_ref(),
+            )?;
+        }
+
+        // written before compiling so that it is available for
+        // fixing compile errors
+        if self.compile_commands {
+            emit_compile_commands(
+                &build,
+                &self.out_dir()?,
+                name,
+                self.compile_commands_dir.as_deref(),
+            )?;
+        }
+
+        let objects = compile_incremental_with(
+            &build,
+            &self.out_dir()?,
+            name,
+            &fingerprint,
+            self.launcher().as_ref(),
+        )?;
+
+        chain!(&self.sources, &self.headers).for_each(print_cargo_rerun_if_changed);
+        print_cargo_rerun_if_changed_deps(&build, &self.out_dir()?, name, &self.includes)?;
+
+        archive_objects(&objects, name)?;
         self.link()?;
         Ok(())
     }
@@ -158,11 +361,32 @@ impl CppExtension {
         Ok(())
     }
 
+    /// Configure the [cc::Build] returned by `cxx_build::bridge()` to
+    /// compile against libtorch.
+    ///
+    /// It applies libtorch include paths, defines and the C++11 ABI
+    /// flag as [configure_cc()](CppExtension::configure_cc) does, and
+    /// adds the include directory of the bundled
+    /// `torch_build/tensor_bridge.h` header. The header lets
+    /// `at::Tensor` cross the bridge as an opaque type behind
+    /// `UniquePtr` or `SharedPtr`. Since the bridge build only
+    /// archives the objects, link paths and libraries are printed as
+    /// Cargo instructions by [link()](CppExtension::link).
+    pub fn configure_cxx_build(&self, build: &mut cc::Build) -> Result<()> {
+        self.configure_cc(build)?;
+        build.include(crate::env::CSRC_INCLUDE_DIR);
+        self.link()?;
+        Ok(())
+    }
+
     #[cfg(any(target_os = "linux", target_os = "macos"))]
     fn configure_cc_unix(&self, build: &mut cc::Build) -> Result<()> {
         let Self {
             use_cuda_api,
             link_python,
+            force_cuda_link,
+            ref rpath_policy,
+            ref extra_rpaths,
             ref sources,
             ref includes,
             ref libraries,
@@ -172,6 +396,7 @@ impl CppExtension {
 
         let libtorch = crate::probe::probe_libtorch()?;
         let cxx11_abi_flag = if libtorch.use_cxx11_abi { "1" } else { "0" };
+        let link_cuda_api = use_cuda_api || force_cuda_link && libtorch.is_cuda_api_available();
 
         build
             .cpp(true)
@@ -179,30 +404,45 @@ impl CppExtension {
             .includes(libtorch.include_paths(use_cuda_api)?)
             .includes(includes)
             .flag("-std=c++14")
-            .flag(&format!("-D_GLIBCXX_USE_CXX11_ABI={}", cxx11_abi_flag))
+            .flag(format!("-D_GLIBCXX_USE_CXX11_ABI={}", cxx11_abi_flag))
             .files(sources);
 
         // link libtorch
-        libtorch.link_paths(use_cuda_api)?.for_each(|path| {
-            build.flag(&format!("-Wl,-rpath={}", path.display()));
-        });
-        libtorch
-            .libraries(use_cuda_api, link_python)?
-            .for_each(|lib| {
-                build.flag(&format!("-l{lib}"));
+        let rpath_dirs = chain!(libtorch.link_paths(link_cuda_api)?, link_searches.clone());
+        super::rpath::rpath_entries_with_extra(rpath_policy, extra_rpaths, rpath_dirs)
+            .iter()
+            .for_each(|entry| {
+                build.flag(format!("-Wl,-rpath={entry}"));
             });
 
+        if force_cuda_link {
+            build.file(libtorch.cuda_dependency_source(link_cuda_api)?);
+
+            libtorch.libraries(false, link_python)?.for_each(|lib| {
+                build.flag(format!("-l{lib}"));
+            });
+            libtorch
+                .cuda_link_args(link_cuda_api)?
+                .iter()
+                .for_each(|arg| {
+                    build.flag(arg);
+                });
+        } else {
+            libtorch
+                .libraries(use_cuda_api, link_python)?
+                .for_each(|lib| {
+                    build.flag(format!("-l{lib}"));
+                });
+        }
+
         // link user-specified libraries
-        link_searches.iter().for_each(|path| {
-            build.flag(&format!("-Wl,-rpath={}", path.display()));
-        });
         libraries.iter().for_each(|lib| {
-            build.flag(&format!("-l{lib}"));
+            build.flag(format!("-l{lib}"));
         });
 
         // link python
         if link_python {
-            configure_cc_python_libs_unix(build)?;
+            configure_cc_python_libs_unix(build, rpath_policy)?;
         }
 
         Ok(())
@@ -231,6 +471,13 @@ impl CppExtension {
         Ok(())
     }
 
+    fn launcher(&self) -> Option<Launcher> {
+        Some(Launcher {
+            cache: self.compiler_cache.clone()?,
+            base_dir: self.compiler_cache_base_dir.clone(),
+        })
+    }
+
     pub fn link(&self) -> Result<()> {
         cfg_if! {
             if #[cfg(any(target_os = "linux", target_os = "macos"))] {
@@ -247,31 +494,59 @@ impl CppExtension {
 
     #[cfg(any(target_os = "linux", target_os = "macos"))]
     fn link_unix(&self) -> Result<()> {
-        use crate::build::utils::{print_cargo_link_library, print_cargo_link_search};
+        use crate::build::{
+            rpath::print_cargo_rpaths,
+            utils::{
+                print_cargo_link_arg, print_cargo_link_library, print_cargo_link_search,
+                write_cuda_dependency_rs,
+            },
+        };
 
         let Self {
             use_cuda_api,
             link_python,
+            force_cuda_link,
+            ref rpath_policy,
+            ref extra_rpaths,
+            ref rpath_targets,
             ref libraries,
             ref link_searches,
             ..
         } = *self;
 
         let libtorch = crate::probe::probe_libtorch()?;
+        let link_cuda_api = use_cuda_api || force_cuda_link && libtorch.is_cuda_api_available();
+        let mut rpath_dirs = vec![];
 
         // link libtorch
-        libtorch.link_paths(use_cuda_api)?.for_each(|path| {
-            print_cargo_link_search(path);
+        libtorch.link_paths(link_cuda_api)?.for_each(|path| {
+            print_cargo_link_search(&path);
+            rpath_dirs.push(path);
         });
-        libtorch
-            .libraries(use_cuda_api, link_python)?
-            .for_each(|library| {
+
+        if force_cuda_link {
+            libtorch.libraries(false, link_python)?.for_each(|library| {
                 print_cargo_link_library(library);
             });
+            libtorch
+                .cuda_link_args(link_cuda_api)?
+                .iter()
+                .for_each(|arg| {
+                    print_cargo_link_arg(arg);
+                });
+            write_cuda_dependency_rs(&self.out_dir()?)?;
+        } else {
+            libtorch
+                .libraries(use_cuda_api, link_python)?
+                .for_each(|library| {
+                    print_cargo_link_library(library);
+                });
+        }
 
         // link user-specified libraries
         link_searches.iter().for_each(|path| {
             print_cargo_link_search(path);
+            rpath_dirs.push(path.clone());
         });
         libraries.iter().for_each(|library| {
             print_cargo_link_library(library);
@@ -279,18 +554,25 @@ impl CppExtension {
 
         // link python
         if link_python {
-            link_python_libs_unix()?;
+            rpath_dirs.extend(link_python_libs_unix()?);
         }
 
+        print_cargo_rpaths(rpath_policy, extra_rpaths, rpath_targets, rpath_dirs);
+
         Ok(())
     }
 
+    /// Configure the [bindgen::Builder] to parse the registered
+    /// headers. The options given by
+    /// [bindgen()](CppExtension::bindgen) are applied as well.
+    #[cfg(feature = "bindgen")]
     pub fn configure_bindgen(&self, builder: bindgen::Builder) -> Result<bindgen::Builder> {
         let Self {
             use_cuda_api,
             link_python,
             ref includes,
             ref headers,
+            ref bindgen,
             ..
         } = *self;
 
@@ -326,6 +608,11 @@ impl CppExtension {
             builder
         };
 
+        let builder = match bindgen {
+            Some(options) => options.configure_bindgen(builder),
+            None => builder,
+        };
+
         Ok(builder)
     }
 }
@@ -337,7 +624,10 @@ impl Default for CppExtension {
 }
 
 #[cfg(any(target_os = "linux", target_os = "macos"))]
-fn configure_cc_python_libs_unix(build: &mut cc::Build) -> Result<()> {
+pub(crate) fn configure_cc_python_libs_unix(
+    build: &mut cc::Build,
+    rpath_policy: &RpathPolicy,
+) -> Result<()> {
     let ProbePython {
         includes,
         link_searches,
@@ -345,21 +635,25 @@ fn configure_cc_python_libs_unix(build: &mut cc::Build) -> Result<()> {
     } = probe_python()?;
     build.includes(includes);
 
-    for path in link_searches {
-        build.flag(&format!("-Wl,-rpath={}", path.display()));
+    for entry in rpath_policy.rpath_entries(link_searches) {
+        build.flag(format!("-Wl,-rpath={entry}"));
     }
 
     for library in libraries {
-        build.flag(&format!("-l{library}"));
+        build.flag(format!("-l{library}"));
     }
 
     Ok(())
 }
 
+/// Print Cargo instructions to link Python libraries and return the
+/// library search directories.
 #[cfg(any(target_os = "linux", target_os = "macos"))]
-fn link_python_libs_unix() -> Result<()> {
+pub(crate) fn link_python_libs_unix() -> Result<Vec<PathBuf>> {
     use crate::build::utils::{print_cargo_link_library, print_cargo_link_search};
 
+    let mut link_searches = vec![];
+
     let output = Command::new("python3-config")
         .arg("--includes")
         .arg("--ldflags")
@@ -377,6 +671,7 @@ fn link_python_libs_unix() -> Result<()> {
             Some("-L") => {
                 let path = &flag[2..];
                 print_cargo_link_search(path);
+                link_searches.push(PathBuf::from(path));
             }
             Some("-l") => {
                 let library = &flag[2..];
@@ -387,5 +682,5 @@ fn link_python_libs_unix() -> Result<()> {
             }
         });
 
-    Ok(())
+    Ok(link_searches)
 }
@@ -1,18 +1,40 @@
 use anyhow::{anyhow, ensure, Context as _, Result};
 use cfg_if::cfg_if;
+use itertools::chain;
 use log::warn;
 use std::{
     env,
+    ffi::OsString,
     path::{Path, PathBuf},
     process::Command,
     str,
 };
 
-use crate::{probe_python, ProbePython};
+use super::cache::Launcher;
+#[cfg(feature = "bindgen")]
+use crate::BindgenOptions;
+use crate::{probe_python, CompilerCache, CudaArchPolicy, LinkTarget, ProbePython, RpathPolicy};
 
 #[derive(Debug, Clone)]
 pub struct CudaExtension {
     link_python: bool,
+    force_cuda_link: bool,
+    rpath_policy: RpathPolicy,
+    extra_rpaths: Vec<String>,
+    rpath_targets: Vec<LinkTarget>,
+    compiler_cache: Option<CompilerCache>,
+    compiler_cache_base_dir: Option<PathBuf>,
+    compile_commands: bool,
+    compile_commands_dir: Option<PathBuf>,
+    cuda_arch_policy: CudaArchPolicy,
+    ptx_only: bool,
+    compress_fatbin: bool,
+    host_compiler: Option<PathBuf>,
+    nvcc_flags: Vec<String>,
+    host_flags: Vec<String>,
+    rdc: bool,
+    #[cfg(feature = "bindgen")]
+    bindgen: Option<BindgenOptions>,
     includes: Vec<PathBuf>,
     link_searches: Vec<PathBuf>,
     libraries: Vec<String>,
@@ -25,6 +47,23 @@ impl CudaExtension {
     pub fn new() -> Self {
         Self {
             link_python: false,
+            force_cuda_link: false,
+            rpath_policy: RpathPolicy::Absolute,
+            extra_rpaths: vec![],
+            rpath_targets: vec![],
+            compiler_cache: None,
+            compiler_cache_base_dir: None,
+            compile_commands: false,
+            compile_commands_dir: None,
+            cuda_arch_policy: CudaArchPolicy::Host,
+            ptx_only: false,
+            compress_fatbin: false,
+            host_compiler: None,
+            nvcc_flags: vec![],
+            host_flags: vec![],
+            rdc: false,
+            #[cfg(feature = "bindgen")]
+            bindgen: None,
             includes: vec![],
             headers: vec![],
             sources: vec![],
@@ -50,6 +89,200 @@ impl CudaExtension {
         self
     }
 
+    /// Force linking to libtorch CUDA libraries.
+    ///
+    /// It compiles the `dummy_cuda_dependency()` function that
+    /// references libtorch CUDA symbols and links CUDA libraries with
+    /// `-Wl,--no-as-needed`. The [link()](CudaExtension::link) writes
+    /// `cuda_dependency.rs` to the output directory, which provides
+    /// `keep_cuda_dependency()` to be called on the Rust side.
+    pub fn force_cuda_link(&mut self, enabled: bool) -> &mut Self {
+        self.force_cuda_link = enabled;
+        self
+    }
+
+    /// Set the policy to embed rpath into linked artifacts. It
+    /// defaults to [RpathPolicy::Absolute].
+    pub fn rpath_policy(&mut self, policy: RpathPolicy) -> &mut Self {
+        self.rpath_policy = policy;
+        self
+    }
+
+    /// Add an rpath entry that is embedded verbatim in addition to
+    /// the entries generated by the
+    /// [rpath_policy()](CudaExtension::rpath_policy).
+    pub fn extra_rpath<S>(&mut self, entry: S) -> &mut Self
+    where
+        S: AsRef<str>,
+    {
+        self.extra_rpaths.push(entry.as_ref().to_owned());
+        self
+    }
+
+    pub fn extra_rpaths<S>(&mut self, entries: S) -> &mut Self
+    where
+        S: IntoIterator,
+        S::Item: AsRef<str>,
+    {
+        self.extra_rpaths
+            .extend(entries.into_iter().map(|s| s.as_ref().to_owned()));
+        self
+    }
+
+    /// Add a kind of Cargo targets that rpath applies to. If no
+    /// target is added, rpath applies to all targets.
+    pub fn rpath_target(&mut self, target: LinkTarget) -> &mut Self {
+        self.rpath_targets.push(target);
+        self
+    }
+
+    pub fn rpath_targets<T>(&mut self, targets: T) -> &mut Self
+    where
+        T: IntoIterator<Item = LinkTarget>,
+    {
+        self.rpath_targets.extend(targets);
+        self
+    }
+
+    /// Run compilers through a compiler cache such as ccache or
+    /// sccache.
+    pub fn compiler_cache(&mut self, cache: CompilerCache) -> &mut Self {
+        self.compiler_cache = Some(cache);
+        self
+    }
+
+    /// Rewrite absolute paths under `dir` in compiler arguments
+    /// relative to the working directory, like `CCACHE_BASEDIR`, so
+    /// that cached results are reused across checkouts in different
+    /// locations. It applies only if
+    /// [compiler_cache()](CudaExtension::compiler_cache) is set.
+    pub fn compiler_cache_base_dir<P>(&mut self, dir: P) -> &mut Self
+    where
+        P: AsRef<Path>,
+    {
+        self.compiler_cache_base_dir = Some(dir.as_ref().to_owned());
+        self
+    }
+
+    /// Write `compile_commands.json` with the compiler invocation of
+    /// each source to the output directory when
+    /// [build()](CudaExtension::build) runs. Tools like clangd read the file
+    /// to find libtorch headers and defines.
+    pub fn compile_commands(&mut self, enabled: bool) -> &mut Self {
+        self.compile_commands = enabled;
+        self
+    }
+
+    /// Also write `compile_commands.json` to a stable directory, such
+    /// as the workspace root. It implies
+    /// [compile_commands(true)](CudaExtension::compile_commands).
+    pub fn compile_commands_dir<P>(&mut self, dir: P) -> &mut Self
+    where
+        P: AsRef<Path>,
+    {
+        self.compile_commands = true;
+        self.compile_commands_dir = Some(dir.as_ref().to_owned());
+        self
+    }
+
+    /// Set the policy to determine CUDA architectures. It defaults
+    /// to [CudaArchPolicy::Host].
+    pub fn cuda_arch_policy(&mut self, policy: CudaArchPolicy) -> &mut Self {
+        self.cuda_arch_policy = policy;
+        self
+    }
+
+    /// Embed only PTX for each architecture without SASS.
+    ///
+    /// The PTX is compiled by the driver when the kernels are first
+    /// loaded, which allows the binary to run on newer GPUs at the
+    /// cost of startup time.
+    pub fn ptx_only(&mut self, enabled: bool) -> &mut Self {
+        self.ptx_only = enabled;
+        self
+    }
+
+    /// Compress the embedded device code with `-Xfatbin
+    /// -compress-all` to reduce the binary size.
+    pub fn compress_fatbin(&mut self, enabled: bool) -> &mut Self {
+        self.compress_fatbin = enabled;
+        self
+    }
+
+    /// Set the host compiler passed to nvcc by `-ccbin`. By default,
+    /// it is the C++ compiler chosen by [cc].
+    pub fn host_compiler<P>(&mut self, path: P) -> &mut Self
+    where
+        P: AsRef<Path>,
+    {
+        self.host_compiler = Some(path.as_ref().to_owned());
+        self
+    }
+
+    /// Add a flag passed to nvcc as it is, such as `--use_fast_math`
+    /// or `-lineinfo`.
+    pub fn nvcc_flag<S>(&mut self, flag: S) -> &mut Self
+    where
+        S: AsRef<str>,
+    {
+        self.nvcc_flags.push(flag.as_ref().to_owned());
+        self
+    }
+
+    pub fn nvcc_flags<S>(&mut self, flags: S) -> &mut Self
+    where
+        S: IntoIterator,
+        S::Item: AsRef<str>,
+    {
+        self.nvcc_flags
+            .extend(flags.into_iter().map(|s| s.as_ref().to_owned()));
+        self
+    }
+
+    /// Add a flag forwarded to the host compiler by `-Xcompiler`,
+    /// such as `-Wall`.
+    pub fn host_flag<S>(&mut self, flag: S) -> &mut Self
+    where
+        S: AsRef<str>,
+    {
+        self.host_flags.push(flag.as_ref().to_owned());
+        self
+    }
+
+    pub fn host_flags<S>(&mut self, flags: S) -> &mut Self
+    where
+        S: IntoIterator,
+        S::Item: AsRef<str>,
+    {
+        self.host_flags
+            .extend(flags.into_iter().map(|s| s.as_ref().to_owned()));
+        self
+    }
+
+    /// Compile relocatable device code with `-rdc=true`, so that
+    /// `__device__` functions can be called across source files.
+    ///
+    /// The device code is linked by
+    /// [build()](CudaExtension::build). If the objects are compiled
+    /// separately after [configure_cc()](CudaExtension::configure_cc),
+    /// call [device_link()](CudaExtension::device_link) over them. The
+    /// [link()](CudaExtension::link) links `cudadevrt` additionally.
+    pub fn rdc(&mut self, enabled: bool) -> &mut Self {
+        self.rdc = enabled;
+        self
+    }
+
+    /// Generate Rust bindings for the registered headers when
+    /// [build()](CudaExtension::build) runs. Bindings are not
+    /// generated unless this is set. Python headers are visible to
+    /// bindgen only if [link_python()](CudaExtension::link_python) is
+    /// enabled.
+    #[cfg(feature = "bindgen")]
+    pub fn bindgen(&mut self, options: BindgenOptions) -> &mut Self {
+        self.bindgen = Some(options);
+        self
+    }
+
     pub fn include<P>(&mut self, path: P) -> &mut Self
     where
         P: AsRef<Path>,
@@ -142,33 +375,110 @@ impl CudaExtension {
 
     /// Compile and link CUDA source code. This is a shorthand for
     /// [configure()](CudaExtension::configure) and then
-    /// [link()](CudaExtension::link).
+    /// [link()](CudaExtension::link). Bindings are generated as well
+    /// if [bindgen()](CudaExtension::bindgen) is set.
     pub fn build(&self, name: &str) -> Result<()> {
+        use super::{
+            incremental::{
+                archive_objects, compile_incremental_with, emit_compile_commands,
+                libtorch_fingerprint, print_cargo_rerun_if_changed_deps,
+            },
+            utils::print_cargo_rerun_if_changed,
+        };
+
         let mut cc_build = cc::Build::new();
         self.configure_cc(&mut cc_build)?;
-        cc_build.try_compile(name)?;
 
-        let bg_build = bindgen::Builder::default();
-        let bg_build = self.configure_bindgen(bg_build)?;
-        let bindings = bg_build.generate()?;
+        // written before compiling so that it is available for
+        // fixing compile errors
+        if self.compile_commands {
+            emit_compile_commands(
+                &cc_build,
+                &self.out_dir()?,
+                name,
+                self.compile_commands_dir.as_deref(),
+            )?;
+        }
+
+        let mut objects = compile_incremental_with(
+            &cc_build,
+            &self.out_dir()?,
+            name,
+            &libtorch_fingerprint()?,
+            self.launcher().as_ref(),
+        )?;
+
+        chain!(&self.sources, &self.headers).for_each(print_cargo_rerun_if_changed);
+        print_cargo_rerun_if_changed_deps(&cc_build, &self.out_dir()?, name, &self.includes)?;
+
+        if self.rdc {
+            objects.push(self.device_link(&cc_build, &objects, name)?);
+        }
+        archive_objects(&objects, name)?;
 
-        let out_dir = self.out_dir()?;
-        let codegen_file = out_dir.join(format!("{name}.rs"));
-        bindings.write_to_file(codegen_file)?;
+        #[cfg(feature = "bindgen")]
+        if self.bindgen.is_some() {
+            self.generate_bindings(name)?;
+        }
 
         self.link()?;
         Ok(())
     }
 
+    /// Link relocatable device code in `objects` into one object
+    /// `{name}_dlink.o` in the output directory and return its path.
+    ///
+    /// The `build` must be configured by
+    /// [configure_cc()](CudaExtension::configure_cc), so that the
+    /// device link uses the same architectures. Only the architecture
+    /// flags of the `build` and the
+    /// [host_compiler()](CudaExtension::host_compiler) are passed to
+    /// nvcc, since libraries are linked in the host link step. The returned object must be
+    /// archived or linked together with `objects`. nvcc runs through
+    /// the [compiler_cache()](CudaExtension::compiler_cache) if set.
+    pub fn device_link<P>(&self, build: &cc::Build, objects: &[P], name: &str) -> Result<PathBuf>
+    where
+        P: AsRef<Path>,
+    {
+        let dlink = self.out_dir()?.join(format!("{name}_dlink.o"));
+        let tool = build.try_get_compiler()?;
+
+        let mut nvcc = Command::new(tool.path());
+        tool.get_envs().for_each(|(key, val)| {
+            nvcc.env(key, val);
+        });
+        if let Some(host_compiler) = &self.host_compiler {
+            nvcc.arg("-ccbin").arg(host_compiler);
+        }
+        nvcc.args(nvcc_arch_args(tool.args()))
+            .arg("-rdc=true")
+            .arg("-Xcompiler")
+            .arg("-fPIC")
+            .arg("--device-link")
+            .arg("-o")
+            .arg(&dlink)
+            .args(objects.iter().map(|path| path.as_ref()));
+        if let Some(launcher) = self.launcher() {
+            nvcc = launcher.wrap(nvcc)?;
+        }
+
+        let status = nvcc
+            .status()
+            .with_context(|| format!("unable to run {nvcc:?}"))?;
+        ensure!(status.success(), "device link failed: {nvcc:?}");
+
+        Ok(dlink)
+    }
+
     /// Configure the [cc::Build] to compile CUDA source code.
     pub fn configure_cc(&self, build: &mut cc::Build) -> Result<()> {
         cfg_if! {
             if #[cfg(any(target_os = "linux", target_os = "macos"))] {
                 self.configure_cc_unix(build)?;
             } else if #[cfg(target_os = "windows")] {
-                bail!("Unsupported OS")l
+                bail!("Unsupported OS")
             } else {
-                bail!("Unsupported OS")l
+                bail!("Unsupported OS")
             }
         }
 
@@ -179,6 +489,16 @@ impl CudaExtension {
     fn configure_cc_unix(&self, build: &mut cc::Build) -> Result<()> {
         let Self {
             link_python: use_python,
+            force_cuda_link,
+            ref rpath_policy,
+            ref extra_rpaths,
+            ref cuda_arch_policy,
+            ptx_only,
+            compress_fatbin,
+            ref host_compiler,
+            ref nvcc_flags,
+            ref host_flags,
+            rdc,
             ref includes,
             ref link_searches,
             ref libraries,
@@ -193,7 +513,7 @@ impl CudaExtension {
         );
 
         let cxx11_abi_flag = if libtorch.use_cxx11_abi { "1" } else { "0" };
-        let cuda_arches = crate::cuda::cuda_arches()?;
+        let cuda_arches = crate::cuda::filter_nvcc_cuda_arches(&cuda_arch_policy.resolve()?)?;
 
         build
             .cuda(true)
@@ -201,48 +521,99 @@ impl CudaExtension {
             .includes(libtorch.include_paths(true)?)
             .includes(includes)
             .flag("-std=c++14")
-            .flag(&format!("-D_GLIBCXX_USE_CXX11_ABI={}", cxx11_abi_flag))
+            .flag(format!("-D_GLIBCXX_USE_CXX11_ABI={}", cxx11_abi_flag))
             .files(sources);
 
+        if force_cuda_link {
+            build.file(libtorch.cuda_dependency_source(true)?);
+        }
+
         // specify CUDA architecture flags
         cuda_arches.iter().for_each(|arch| {
-            build.flag(&arch.nvcc_flag());
+            if ptx_only {
+                build.flag(arch.nvcc_ptx_flag());
+            } else {
+                build.flag(arch.nvcc_flag());
+            }
         });
+        if compress_fatbin {
+            build.flag("-Xfatbin").flag("-compress-all");
+        }
 
-        // utilities
-        let add_link_search = |build: &mut cc::Build, path: &Path| {
+        // specify the host compiler
+        if let Some(host_compiler) = host_compiler {
             build
-                .flag("-Xlinker")
-                .flag(&format!("-Wl,-rpath={}", path.display()));
+                .ccbin(false)
+                .flag("-ccbin")
+                .flag(host_compiler.display().to_string());
+        }
+
+        // user-specified flags
+        nvcc_flags.iter().for_each(|flag| {
+            build.flag(flag);
+        });
+        host_flags.iter().for_each(|flag| {
+            build.flag("-Xcompiler").flag(flag);
+        });
+
+        // generate relocatable device code
+        if rdc {
+            build.flag("-rdc=true");
+        }
+
+        // utilities
+        let add_rpath = |build: &mut cc::Build, entry: &str| {
+            add_linker_arg(build, &format!("-Wl,-rpath,{entry}"));
         };
         let add_library = |build: &mut cc::Build, name: &str| {
-            build.flag(&format!("-l{name}"));
+            build.flag(format!("-l{name}"));
         };
 
         // link libtorch
-        libtorch.link_paths(true)?.for_each(|path| {
-            add_link_search(build, &path);
-        });
-        libtorch.libraries(true, use_python)?.for_each(|library| {
-            add_library(build, library);
-        });
+        let rpath_dirs = chain!(libtorch.link_paths(true)?, link_searches.clone());
+        super::rpath::rpath_entries_with_extra(rpath_policy, extra_rpaths, rpath_dirs)
+            .iter()
+            .for_each(|entry| {
+                add_rpath(build, entry);
+            });
+
+        if force_cuda_link {
+            libtorch.libraries(false, use_python)?.for_each(|library| {
+                add_library(build, library);
+            });
+            libtorch.cuda_link_args(true)?.iter().for_each(|arg| {
+                add_linker_arg(build, arg);
+            });
+        } else {
+            libtorch.libraries(true, use_python)?.for_each(|library| {
+                add_library(build, library);
+            });
+        }
+
+        if rdc {
+            add_library(build, "cudadevrt");
+        }
 
         // link user-specified libraries
         libraries.iter().for_each(|library| {
             add_library(build, library);
         });
-        link_searches.iter().for_each(|path| {
-            add_link_search(build, path);
-        });
 
         // link python
         if use_python {
-            configure_cc_python_libs_unix(build)?;
+            configure_cc_python_libs_unix(build, rpath_policy)?;
         }
 
         Ok(())
     }
 
+    fn launcher(&self) -> Option<Launcher> {
+        Some(Launcher {
+            cache: self.compiler_cache.clone()?,
+            base_dir: self.compiler_cache_base_dir.clone(),
+        })
+    }
+
     pub fn link(&self) -> Result<()> {
         cfg_if! {
             if #[cfg(any(target_os = "linux", target_os = "macos"))] {
@@ -259,10 +630,21 @@ impl CudaExtension {
 
     #[cfg(any(target_os = "linux", target_os = "macos"))]
     fn link_unix(&self) -> Result<()> {
-        use crate::build::utils::{print_cargo_link_library, print_cargo_link_search};
+        use crate::build::{
+            rpath::print_cargo_rpaths,
+            utils::{
+                print_cargo_link_arg, print_cargo_link_library, print_cargo_link_search,
+                write_cuda_dependency_rs,
+            },
+        };
 
         let Self {
             link_python,
+            force_cuda_link,
+            rdc,
+            ref rpath_policy,
+            ref extra_rpaths,
+            ref rpath_targets,
             ref link_searches,
             ref libraries,
             ..
@@ -273,34 +655,80 @@ impl CudaExtension {
             libtorch.is_cuda_api_available(),
             "CUDA runtime is not supported by PyTorch"
         );
+        let mut rpath_dirs = vec![];
 
         // link libtorch
         libtorch.link_paths(true)?.for_each(|path| {
-            print_cargo_link_search(path);
-        });
-        libtorch.libraries(true, link_python)?.for_each(|library| {
-            print_cargo_link_library(library);
+            print_cargo_link_search(&path);
+            rpath_dirs.push(path);
         });
 
+        if force_cuda_link {
+            libtorch.libraries(false, link_python)?.for_each(|library| {
+                print_cargo_link_library(library);
+            });
+            libtorch.cuda_link_args(true)?.iter().for_each(|arg| {
+                print_cargo_link_arg(arg);
+            });
+            write_cuda_dependency_rs(&self.out_dir()?)?;
+        } else {
+            libtorch.libraries(true, link_python)?.for_each(|library| {
+                print_cargo_link_library(library);
+            });
+        }
+
+        // link the device runtime
+        if rdc {
+            print_cargo_rdc_libraries();
+        }
+
         // link user-specified libraries
         libraries.iter().for_each(|library| {
             print_cargo_link_library(library);
         });
         link_searches.iter().for_each(|path| {
             print_cargo_link_search(path);
+            rpath_dirs.push(path.clone());
         });
 
         // link python
         if link_python {
-            link_python_libs_unix()?;
+            rpath_dirs.extend(link_python_libs_unix()?);
         }
 
+        print_cargo_rpaths(rpath_policy, extra_rpaths, rpath_targets, rpath_dirs);
+
         Ok(())
     }
 
+    /// Generate Rust bindings for the registered headers to the
+    /// output directory with the options given by
+    /// [bindgen()](CudaExtension::bindgen), and return the path of
+    /// the generated file.
+    #[cfg(feature = "bindgen")]
+    pub fn generate_bindings(&self, name: &str) -> Result<PathBuf> {
+        let options = self.bindgen.clone().unwrap_or_default();
+        let bindings = self
+            .configure_bindgen(bindgen::Builder::default())?
+            .generate()?;
+
+        let codegen_file = self.out_dir()?.join(options.output_file(name));
+        bindings.write_to_file(&codegen_file)?;
+        Ok(codegen_file)
+    }
+
+    /// Configure the [bindgen::Builder] to parse the registered
+    /// headers. The options given by
+    /// [bindgen()](CudaExtension::bindgen), such as
+    /// [BindgenOptions::tch_preset()], are applied as well.
+    #[cfg(feature = "bindgen")]
     pub fn configure_bindgen(&self, builder: bindgen::Builder) -> Result<bindgen::Builder> {
         let Self {
-            includes, headers, ..
+            link_python,
+            includes,
+            headers,
+            bindgen,
+            ..
         } = self;
 
         // Probe libtorch
@@ -326,19 +754,60 @@ impl CudaExtension {
                 builder.clang_arg(format!("-I{}", path.display()))
             });
 
-        let ProbePython {
-            includes: python_includes,
-            ..
-        } = probe_python()?;
+        let builder = if *link_python {
+            let ProbePython {
+                includes: python_includes,
+                ..
+            } = probe_python()?;
 
-        let builder = python_includes.into_iter().fold(builder, |builder, path| {
-            builder.clang_arg(format!("-I{}", path.display()))
-        });
+            python_includes.into_iter().fold(builder, |builder, path| {
+                builder.clang_arg(format!("-I{}", path.display()))
+            })
+        } else {
+            builder
+        };
+
+        let builder = match bindgen {
+            Some(options) => options.configure_bindgen(builder),
+            None => builder,
+        };
 
         Ok(builder)
     }
 }
 
+/// Pick the nvcc flags that select GPU architectures, such as
+/// `-gencode=arch=compute_86,code=sm_86` or `-arch sm_86`.
+fn nvcc_arch_args(args: &[OsString]) -> Vec<OsString> {
+    const FLAGS: &[&str] = &[
+        "-gencode",
+        "--generate-code",
+        "-arch",
+        "--gpu-architecture",
+        "-code",
+        "--gpu-code",
+    ];
+
+    let mut picked = vec![];
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        let Some(text) = arg.to_str() else {
+            continue;
+        };
+        if FLAGS.contains(&text) {
+            // the value is given in the next argument
+            picked.push(arg.clone());
+            picked.extend(args.next().cloned());
+        } else if FLAGS.iter().any(|flag| {
+            text.strip_prefix(flag)
+                .is_some_and(|rest| rest.starts_with('='))
+        }) {
+            picked.push(arg.clone());
+        }
+    }
+    picked
+}
+
 impl Default for CudaExtension {
     fn default() -> Self {
         Self::new()
@@ -346,7 +815,7 @@ impl Default for CudaExtension {
 }
 
 #[cfg(any(target_os = "linux", target_os = "macos"))]
-fn configure_cc_python_libs_unix(build: &mut cc::Build) -> Result<()> {
+fn configure_cc_python_libs_unix(build: &mut cc::Build, rpath_policy: &RpathPolicy) -> Result<()> {
     let ProbePython {
         includes,
         link_searches,
@@ -354,23 +823,51 @@ fn configure_cc_python_libs_unix(build: &mut cc::Build) -> Result<()> {
     } = probe_python()?;
     build.includes(includes);
 
-    for path in link_searches {
-        build
-            .flag("-Xlinker")
-            .flag(&format!("-Wl,-rpath={}", path.display()));
+    for entry in rpath_policy.rpath_entries(link_searches) {
+        add_linker_arg(build, &format!("-Wl,-rpath,{entry}"));
     }
 
     for library in libraries {
-        build.flag(&format!("-l{library}"));
+        build.flag(format!("-l{library}"));
     }
 
     Ok(())
 }
 
+/// Print Cargo instructions to link the CUDA device runtime, which
+/// is required by relocatable device code.
+pub(crate) fn print_cargo_rdc_libraries() {
+    use crate::build::utils::print_cargo_link_library;
+
+    print_cargo_link_library("static=cudadevrt");
+    print_cargo_link_library("cudart");
+}
+
+/// Pass a linker argument to nvcc.
+///
+/// A `-Wl,` argument is split by commas and each part is passed by a
+/// separate `-Xlinker`, because nvcc splits `-Xlinker` values by
+/// commas as well. Other arguments are passed as they are.
 #[cfg(any(target_os = "linux", target_os = "macos"))]
-fn link_python_libs_unix() -> Result<()> {
+fn add_linker_arg(build: &mut cc::Build, arg: &str) {
+    match arg.strip_prefix("-Wl,") {
+        Some(args) => args.split(',').for_each(|arg| {
+            build.flag("-Xlinker").flag(arg);
+        }),
+        None => {
+            build.flag(arg);
+        }
+    }
+}
+
+/// Print Cargo instructions to link Python libraries and return the
+/// library search directories.
+#[cfg(any(target_os = "linux", target_os = "macos"))]
+fn link_python_libs_unix() -> Result<Vec<PathBuf>> {
     use crate::build::utils::{print_cargo_link_library, print_cargo_link_search};
 
+    let mut link_searches = vec![];
+
     let output = Command::new("python3-config")
         .arg("--includes")
         .arg("--ldflags")
@@ -388,6 +885,7 @@ fn link_python_libs_unix() -> Result<()> {
             Some("-L") => {
                 let path = &flag[2..];
                 print_cargo_link_search(path);
+                link_searches.push(PathBuf::from(path));
             }
             Some("-l") => {
                 let library = &flag[2..];
@@ -398,5 +896,41 @@ fn link_python_libs_unix() -> Result<()> {
             }
         });
 
-    Ok(())
+    Ok(link_searches)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::nvcc_arch_args;
+    use std::ffi::OsString;
+
+    #[test]
+    fn pick_nvcc_arch_args() {
+        let args: Vec<OsString> = [
+            "-I/opt/libtorch/include",
+            "-gencode=arch=compute_80,code=sm_80",
+            "-Xcompiler",
+            "-fPIC",
+            "-gencode",
+            "arch=compute_90,code=[sm_90,compute_90]",
+            "-ltorch_cuda",
+            "-Xlinker",
+            "-rpath",
+            "-arch=sm_86",
+            "-architecture",
+        ]
+        .into_iter()
+        .map(OsString::from)
+        .collect();
+
+        assert_eq!(
+            nvcc_arch_args(&args),
+            [
+                "-gencode=arch=compute_80,code=sm_80",
+                "-gencode",
+                "arch=compute_90,code=[sm_90,compute_90]",
+                "-arch=sm_86",
+            ]
+        );
+    }
 }
@@ -0,0 +1,611 @@
+use super::cache::Launcher;
+use crate::{
+    env::PYTORCH_ROCM_ARCH,
+    hipify::{hipify_dir, hipify_file_tree},
+    library::{Api, HipApi},
+    CompilerCache, LinkTarget, RpathPolicy,
+};
+use anyhow::{anyhow, bail, ensure, Result};
+use cfg_if::cfg_if;
+use itertools::chain;
+use std::{
+    env,
+    path::{Path, PathBuf},
+    process::Command,
+    str,
+};
+
+/// Build CUDA sources for ROCm.
+///
+/// Sources and files in include directories are translated to HIP by
+/// [hipify](crate::hipify) into the output directory, and then
+/// compiled by `hipcc` with `--offload-arch` targets.
+#[derive(Debug, Clone)]
+pub struct HipExtension {
+    link_python: bool,
+    rpath_policy: RpathPolicy,
+    extra_rpaths: Vec<String>,
+    rpath_targets: Vec<LinkTarget>,
+    hip_compiler: Option<PathBuf>,
+    offload_arches: Vec<String>,
+    compiler_cache: Option<CompilerCache>,
+    compiler_cache_base_dir: Option<PathBuf>,
+    compile_commands: bool,
+    compile_commands_dir: Option<PathBuf>,
+    includes: Vec<PathBuf>,
+    link_searches: Vec<PathBuf>,
+    libraries: Vec<String>,
+    headers: Vec<PathBuf>,
+    sources: Vec<PathBuf>,
+    out_dir: Option<PathBuf>,
+}
+
+impl HipExtension {
+    pub fn new() -> Self {
+        Self {
+            link_python: false,
+            rpath_policy: RpathPolicy::Absolute,
+            extra_rpaths: vec![],
+            rpath_targets: vec![],
+            hip_compiler: None,
+            offload_arches: vec![],
+            compiler_cache: None,
+            compiler_cache_base_dir: None,
+            compile_commands: false,
+            compile_commands_dir: None,
+            includes: vec![],
+            headers: vec![],
+            sources: vec![],
+            link_searches: vec![],
+            libraries: vec![],
+            out_dir: None,
+        }
+    }
+
+    pub fn out_dir(&self) -> Result<PathBuf> {
+        Ok(match &self.out_dir {
+            Some(dir) => dir.clone(),
+            None => {
+                let out_dir = env::var_os("OUT_DIR")
+                    .ok_or_else(|| anyhow!("OUT_DIR environment variable is not set"))?;
+                PathBuf::from(out_dir)
+            }
+        })
+    }
+
+    pub fn link_python(&mut self, enabled: bool) -> &mut Self {
+        self.link_python = enabled;
+        self
+    }
+
+    /// Set the policy to embed rpath into linked artifacts. It
+    /// defaults to [RpathPolicy::Absolute].
+    pub fn rpath_policy(&mut self, policy: RpathPolicy) -> &mut Self {
+        self.rpath_policy = policy;
+        self
+    }
+
+    /// Add an rpath entry that is embedded verbatim in addition to
+    /// the entries generated by the
+    /// [rpath_policy()](HipExtension::rpath_policy).
+    pub fn extra_rpath<S>(&mut self, entry: S) -> &mut Self
+    where
+        S: AsRef<str>,
+    {
+        self.extra_rpaths.push(entry.as_ref().to_owned());
+        self
+    }
+
+    pub fn extra_rpaths<S>(&mut self, entries: S) -> &mut Self
+    where
+        S: IntoIterator,
+        S::Item: AsRef<str>,
+    {
+        self.extra_rpaths
+            .extend(entries.into_iter().map(|s| s.as_ref().to_owned()));
+        self
+    }
+
+    /// Add a kind of Cargo targets that rpath applies to. If no
+    /// target is added, rpath applies to all targets.
+    pub fn rpath_target(&mut self, target: LinkTarget) -> &mut Self {
+        self.rpath_targets.push(target);
+        self
+    }
+
+    pub fn rpath_targets<T>(&mut self, targets: T) -> &mut Self
+    where
+        T: IntoIterator<Item = LinkTarget>,
+    {
+        self.rpath_targets.extend(targets);
+        self
+    }
+
+    /// Set the HIP compiler, such as `hipcc` or `amdclang++`. It
+    /// defaults to `hipcc` in the ROCm installation.
+    pub fn hip_compiler<P>(&mut self, path: P) -> &mut Self
+    where
+        P: AsRef<Path>,
+    {
+        self.hip_compiler = Some(path.as_ref().to_owned());
+        self
+    }
+
+    /// Add an AMD GPU architecture, such as `gfx90a`.
+    ///
+    /// If no architecture is added, they are given by
+    /// `PYTORCH_ROCM_ARCH` environment variable, or detected by
+    /// `rocm_agent_enumerator`.
+    pub fn offload_arch<S>(&mut self, arch: S) -> &mut Self
+    where
+        S: AsRef<str>,
+    {
+        self.offload_arches.push(arch.as_ref().to_owned());
+        self
+    }
+
+    pub fn offload_arches<S>(&mut self, arches: S) -> &mut Self
+    where
+        S: IntoIterator,
+        S::Item: AsRef<str>,
+    {
+        self.offload_arches
+            .extend(arches.into_iter().map(|s| s.as_ref().to_owned()));
+        self
+    }
+
+    /// Run hipcc through a compiler cache such as ccache or sccache.
+    pub fn compiler_cache(&mut self, cache: CompilerCache) -> &mut Self {
+        self.compiler_cache = Some(cache);
+        self
+    }
+
+    /// Rewrite absolute paths under `dir` in compiler arguments. See
+    /// [CudaExtension::compiler_cache_base_dir()](crate::CudaExtension::compiler_cache_base_dir).
+    pub fn compiler_cache_base_dir<P>(&mut self, dir: P) -> &mut Self
+    where
+        P: AsRef<Path>,
+    {
+        self.compiler_cache_base_dir = Some(dir.as_ref().to_owned());
+        self
+    }
+
+    /// Write `compile_commands.json` with the compiler invocation of
+    /// each hipified source to the output directory when
+    /// [build()](HipExtension::build) runs.
+    pub fn compile_commands(&mut self, enabled: bool) -> &mut Self {
+        self.compile_commands = enabled;
+        self
+    }
+
+    /// Also write `compile_commands.json` to a stable directory, such
+    /// as the workspace root. It implies
+    /// [compile_commands(true)](HipExtension::compile_commands).
+    pub fn compile_commands_dir<P>(&mut self, dir: P) -> &mut Self
+    where
+        P: AsRef<Path>,
+    {
+        self.compile_commands = true;
+        self.compile_commands_dir = Some(dir.as_ref().to_owned());
+        self
+    }
+
+    pub fn include<P>(&mut self, path: P) -> &mut Self
+    where
+        P: AsRef<Path>,
+    {
+        self.includes.push(path.as_ref().to_owned());
+        self
+    }
+
+    pub fn includes<P>(&mut self, paths: P) -> &mut Self
+    where
+        P: IntoIterator,
+        P::Item: AsRef<Path>,
+    {
+        self.includes
+            .extend(paths.into_iter().map(|p| p.as_ref().to_owned()));
+        self
+    }
+
+    pub fn header<P>(&mut self, path: P) -> &mut Self
+    where
+        P: AsRef<Path>,
+    {
+        self.headers.push(path.as_ref().to_owned());
+        self
+    }
+
+    pub fn headers<P>(&mut self, paths: P) -> &mut Self
+    where
+        P: IntoIterator,
+        P::Item: AsRef<Path>,
+    {
+        self.headers
+            .extend(paths.into_iter().map(|p| p.as_ref().to_owned()));
+        self
+    }
+
+    pub fn source<P>(&mut self, path: P) -> &mut Self
+    where
+        P: AsRef<Path>,
+    {
+        self.sources.push(path.as_ref().to_owned());
+        self
+    }
+
+    pub fn sources<P>(&mut self, paths: P) -> &mut Self
+    where
+        P: IntoIterator,
+        P::Item: AsRef<Path>,
+    {
+        self.sources
+            .extend(paths.into_iter().map(|p| p.as_ref().to_owned()));
+        self
+    }
+
+    pub fn link_search<P>(&mut self, path: P) -> &mut Self
+    where
+        P: AsRef<Path>,
+    {
+        self.link_searches.push(path.as_ref().to_owned());
+        self
+    }
+
+    pub fn link_searches<P>(&mut self, paths: P) -> &mut Self
+    where
+        P: IntoIterator,
+        P::Item: AsRef<Path>,
+    {
+        self.link_searches
+            .extend(paths.into_iter().map(|p| p.as_ref().to_owned()));
+        self
+    }
+
+    pub fn library<P>(&mut self, name: P) -> &mut Self
+    where
+        P: AsRef<str>,
+    {
+        self.libraries.push(name.as_ref().to_owned());
+        self
+    }
+
+    pub fn libraries<P>(&mut self, names: P) -> &mut Self
+    where
+        P: IntoIterator,
+        P::Item: AsRef<str>,
+    {
+        self.libraries
+            .extend(names.into_iter().map(|p| p.as_ref().to_owned()));
+        self
+    }
+
+    /// Compile and link HIP source code. This is a shorthand for
+    /// [configure_cc()](HipExtension::configure_cc) and then
+    /// [link()](HipExtension::link).
+    pub fn build(&self, name: &str) -> Result<()> {
+        use super::{
+            incremental::{
+                archive_objects, compile_incremental_with, emit_compile_commands,
+                libtorch_fingerprint,
+            },
+            utils::print_cargo_rerun_if_changed,
+        };
+
+        let mut build = cc::Build::new();
+        self.configure_cc(&mut build)?;
+
+        // written before compiling so that it is available for
+        // fixing compile errors
+        if self.compile_commands {
+            emit_compile_commands(
+                &build,
+                &self.out_dir()?,
+                name,
+                self.compile_commands_dir.as_deref(),
+            )?;
+        }
+
+        let objects = compile_incremental_with(
+            &build,
+            &self.out_dir()?,
+            name,
+            &libtorch_fingerprint()?,
+            self.launcher().as_ref(),
+        )?;
+
+        // The sources and include directories are hipified into the
+        // output directory, so track the originals.
+        chain!(&self.sources, &self.headers, &self.includes).for_each(print_cargo_rerun_if_changed);
+
+        archive_objects(&objects, name)?;
+        self.link()?;
+        Ok(())
+    }
+
+    /// Configure the [cc::Build] to compile CUDA source code for
+    /// ROCm. The sources are translated to HIP in this step.
+    pub fn configure_cc(&self, build: &mut cc::Build) -> Result<()> {
+        cfg_if! {
+            if #[cfg(target_os = "linux")] {
+                self.configure_cc_unix(build)?;
+            } else {
+                bail!("Unsupported OS")
+            }
+        }
+
+        Ok(())
+    }
+
+    #[cfg(target_os = "linux")]
+    fn configure_cc_unix(&self, build: &mut cc::Build) -> Result<()> {
+        use super::cpp::configure_cc_python_libs_unix;
+
+        let Self {
+            link_python,
+            ref rpath_policy,
+            ref extra_rpaths,
+            ref hip_compiler,
+            ref includes,
+            ref link_searches,
+            ref libraries,
+            ref sources,
+            ..
+        } = *self;
+
+        let libtorch = crate::probe::probe_libtorch()?;
+        let Api::Hip(HipApi { rocm_home, .. }) = &libtorch.api else {
+            bail!("ROCm is not supported by PyTorch");
+        };
+
+        let cxx11_abi_flag = if libtorch.use_cxx11_abi { "1" } else { "0" };
+        let offload_arches = self.resolve_offload_arches(rocm_home)?;
+        let hip_compiler = match hip_compiler {
+            Some(path) => path.clone(),
+            None => rocm_home.join("bin").join("hipcc"),
+        };
+
+        // translate sources and headers to HIP
+        let hipify_dir = self.out_dir()?.join("hipify");
+        let hip_includes: Vec<_> = includes
+            .iter()
+            .enumerate()
+            .map(|(index, dir)| -> Result<_> {
+                let dst_dir = hipify_dir.join(format!("include{index}"));
+                hipify_dir_if_exists(dir, &dst_dir)?;
+                Ok(dst_dir)
+            })
+            .collect::<Result<_>>()?;
+        // Sources are mirrored with the headers they include by
+        // relative paths, so that these includes still resolve.
+        let hip_sources: Vec<_> = sources
+            .iter()
+            .map(|src| -> Result<_> {
+                let (dst, headers) = hipify_file_tree(src, hipify_dir.join("src"), "hip")?;
+                headers
+                    .iter()
+                    .for_each(super::utils::print_cargo_rerun_if_changed);
+                Ok(dst)
+            })
+            .collect::<Result<_>>()?;
+
+        build
+            .cpp(true)
+            .pic(true)
+            .compiler(hip_compiler)
+            .includes(libtorch.include_paths(true)?)
+            .includes(hip_includes)
+            .includes(includes)
+            .flag("-x")
+            .flag("hip")
+            .flag("-std=c++14")
+            .flag("-fno-gpu-rdc")
+            .flag(format!("-D_GLIBCXX_USE_CXX11_ABI={}", cxx11_abi_flag))
+            .define("__HIP_PLATFORM_AMD__", "1")
+            .define("USE_ROCM", "1")
+            .files(hip_sources);
+
+        // specify GPU architectures
+        offload_arches.iter().for_each(|arch| {
+            build.flag(format!("--offload-arch={arch}"));
+        });
+
+        // link libtorch
+        let rpath_dirs = chain!(libtorch.link_paths(true)?, link_searches.clone());
+        super::rpath::rpath_entries_with_extra(rpath_policy, extra_rpaths, rpath_dirs)
+            .iter()
+            .for_each(|entry| {
+                build.flag(format!("-Wl,-rpath={entry}"));
+            });
+        libtorch.libraries(true, link_python)?.for_each(|library| {
+            build.flag(format!("-l{library}"));
+        });
+
+        // link user-specified libraries
+        libraries.iter().for_each(|library| {
+            build.flag(format!("-l{library}"));
+        });
+
+        // link python
+        if link_python {
+            configure_cc_python_libs_unix(build, rpath_policy)?;
+        }
+
+        Ok(())
+    }
+
+    /// Get the GPU architectures to compile for.
+    fn resolve_offload_arches(&self, rocm_home: &Path) -> Result<Vec<String>> {
+        if !self.offload_arches.is_empty() {
+            return Ok(self.offload_arches.clone());
+        }
+
+        if let Some(arches) = &*PYTORCH_ROCM_ARCH {
+            ensure!(!arches.is_empty(), "PYTORCH_ROCM_ARCH is empty");
+            return Ok(arches.clone());
+        }
+
+        let arches = rocm_arches(rocm_home)?;
+        ensure!(
+            !arches.is_empty(),
+            "no AMD GPU is detected, please set PYTORCH_ROCM_ARCH environment variable"
+        );
+        Ok(arches)
+    }
+
+    fn launcher(&self) -> Option<Launcher> {
+        Some(Launcher {
+            cache: self.compiler_cache.clone()?,
+            base_dir: self.compiler_cache_base_dir.clone(),
+        })
+    }
+
+    pub fn link(&self) -> Result<()> {
+        cfg_if! {
+            if #[cfg(target_os = "linux")] {
+                self.link_unix()?
+            } else {
+                bail!("Unsupported OS")
+            }
+        }
+
+        Ok(())
+    }
+
+    #[cfg(target_os = "linux")]
+    fn link_unix(&self) -> Result<()> {
+        use super::cpp::link_python_libs_unix;
+        use crate::build::{
+            rpath::print_cargo_rpaths,
+            utils::{print_cargo_link_library, print_cargo_link_search},
+        };
+
+        let Self {
+            link_python,
+            ref rpath_policy,
+            ref extra_rpaths,
+            ref rpath_targets,
+            ref link_searches,
+            ref libraries,
+            ..
+        } = *self;
+
+        let libtorch = crate::probe::probe_libtorch()?;
+        ensure!(libtorch.api.is_hip(), "ROCm is not supported by PyTorch");
+        let mut rpath_dirs = vec![];
+
+        // link libtorch
+        libtorch.link_paths(true)?.for_each(|path| {
+            print_cargo_link_search(&path);
+            rpath_dirs.push(path);
+        });
+        libtorch.libraries(true, link_python)?.for_each(|library| {
+            print_cargo_link_library(library);
+        });
+
+        // link user-specified libraries
+        libraries.iter().for_each(|library| {
+            print_cargo_link_library(library);
+        });
+        link_searches.iter().for_each(|path| {
+            print_cargo_link_search(path);
+            rpath_dirs.push(path.clone());
+        });
+
+        // link python
+        if link_python {
+            rpath_dirs.extend(link_python_libs_unix()?);
+        }
+
+        print_cargo_rpaths(rpath_policy, extra_rpaths, rpath_targets, rpath_dirs);
+
+        Ok(())
+    }
+
+    #[cfg(feature = "bindgen")]
+    pub fn configure_bindgen(&self, builder: bindgen::Builder) -> Result<bindgen::Builder> {
+        use crate::{probe_python, ProbePython};
+
+        let Self {
+            link_python,
+            ref includes,
+            ref headers,
+            ..
+        } = *self;
+
+        // Probe libtorch
+        let libtorch = crate::probe::probe_libtorch()?;
+        ensure!(libtorch.api.is_hip(), "ROCm is not supported by PyTorch");
+
+        let builder = builder.clang_args(["-x", "c++"]);
+
+        let builder = headers.iter().fold(builder, |builder, header| {
+            builder.header(format!("{}", header.display()))
+        });
+
+        let builder = includes.iter().fold(builder, |builder, path| {
+            builder.clang_arg(format!("-I{}", path.display()))
+        });
+
+        let builder = libtorch
+            .include_paths(true)?
+            .fold(builder, |builder, path| {
+                builder.clang_arg(format!("-I{}", path.display()))
+            });
+
+        let builder = if link_python {
+            let ProbePython {
+                includes: python_includes,
+                ..
+            } = probe_python()?;
+
+            python_includes.into_iter().fold(builder, |builder, path| {
+                builder.clang_arg(format!("-I{}", path.display()))
+            })
+        } else {
+            builder
+        };
+
+        Ok(builder)
+    }
+}
+
+impl Default for HipExtension {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Detect AMD GPU architectures on the host system using
+/// `rocm_agent_enumerator`.
+pub fn rocm_arches(rocm_home: &Path) -> Result<Vec<String>> {
+    let program = rocm_home.join("bin").join("rocm_agent_enumerator");
+    let output = Command::new(&program).output()?;
+    ensure!(
+        output.status.success(),
+        "unable to run {}",
+        program.display()
+    );
+
+    let stdout = str::from_utf8(&output.stdout)?;
+    let mut arches: Vec<String> = vec![];
+    stdout
+        .lines()
+        .map(|line| line.trim())
+        // gfx000 is the CPU agent
+        .filter(|arch| arch.starts_with("gfx") && *arch != "gfx000")
+        .for_each(|arch| {
+            if !arches.iter().any(|a| a == arch) {
+                arches.push(arch.to_string());
+            }
+        });
+
+    Ok(arches)
+}
+
+fn hipify_dir_if_exists(src_dir: &Path, dst_dir: &Path) -> Result<()> {
+    if src_dir.is_dir() {
+        hipify_dir(src_dir, dst_dir)?;
+    }
+    Ok(())
+}
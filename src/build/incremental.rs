@@ -0,0 +1,520 @@
+use super::cache::Launcher;
+use anyhow::{anyhow, bail, ensure, Context as _, Result};
+use itertools::chain;
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::{hash_map::DefaultHasher, BTreeSet},
+    env, fs,
+    hash::{Hash, Hasher},
+    io,
+    num::NonZeroUsize,
+    path::{Path, PathBuf},
+    process::Command,
+    sync::atomic::{AtomicUsize, Ordering},
+    thread,
+    time::{Duration, SystemTime},
+};
+
+/// Compile each source file in the [cc::Build] into its own object
+/// and return the paths of objects.
+///
+/// Objects are cached in `{out_dir}/objects/{name}`. A source is
+/// recompiled only if the source or any header recorded in its
+/// depfile is newer than the object, or the compiler command or the
+/// `fingerprint` changes. Compilers run in parallel under the Cargo
+/// jobserver.
+///
+/// The compiler must accept `-MD -MF <depfile>`, which is the case
+/// for gcc, clang, hipcc and nvcc.
+pub fn compile_incremental(
+    build: &cc::Build,
+    out_dir: &Path,
+    name: &str,
+    fingerprint: &str,
+) -> Result<Vec<PathBuf>> {
+    compile_incremental_with(build, out_dir, name, fingerprint, None)
+}
+
+/// [compile_incremental()] that runs compilers through the compiler
+/// cache of the `launcher` if given.
+pub(crate) fn compile_incremental_with(
+    build: &cc::Build,
+    out_dir: &Path,
+    name: &str,
+    fingerprint: &str,
+    launcher: Option<&Launcher>,
+) -> Result<Vec<PathBuf>> {
+    let tool = build.try_get_compiler()?;
+    ensure!(
+        !tool.is_like_msvc(),
+        "incremental compilation does not support MSVC-like compilers"
+    );
+
+    let obj_dir = out_dir.join("objects").join(name);
+    fs::create_dir_all(&obj_dir)
+        .with_context(|| format!("unable to create directory {}", obj_dir.display()))?;
+
+    // The stamp records everything that affects the output besides
+    // the input files.
+    let stamp = {
+        let mut stamp = format!("{:?}\n", tool.path());
+        tool.args()
+            .iter()
+            .for_each(|arg| stamp.push_str(&format!("{arg:?}\n")));
+        tool.get_envs()
+            .for_each(|(key, val)| stamp.push_str(&format!("{key:?}={val:?}\n")));
+        stamp.push_str(fingerprint);
+        stamp
+    };
+
+    let jobs: Vec<Job> = build
+        .get_files()
+        .map(|src| Job::new(src, &obj_dir))
+        .collect();
+    let stale: Vec<&Job> = jobs
+        .iter()
+        .filter(|job| !job.is_up_to_date(&stamp))
+        .collect();
+
+    if !stale.is_empty() {
+        let client = jobserver_client()?;
+
+        let next = AtomicUsize::new(0);
+
+        // Each worker takes jobs from the queue until it is empty.
+        let work = || -> Result<()> {
+            let mut first_error = None;
+            loop {
+                let index = next.fetch_add(1, Ordering::SeqCst);
+                let Some(job) = stale.get(index) else {
+                    break;
+                };
+                let cmd = job.command(tool.to_command());
+                let result = match launcher {
+                    Some(launcher) => launcher.wrap(cmd),
+                    None => Ok(cmd),
+                }
+                .and_then(|cmd| job.run(cmd, &stamp));
+                if let Err(err) = result {
+                    first_error.get_or_insert(err);
+                }
+            }
+            match first_error {
+                Some(err) => Err(err),
+                None => Ok(()),
+            }
+        };
+
+        thread::scope(|scope| -> Result<()> {
+            // This process holds an implicit token for the first
+            // worker. Extra workers are started only when a token is
+            // available, so that no worker waits for a token that is
+            // never released.
+            let mut handles = vec![scope.spawn(work)];
+            while handles.len() < stale.len() && next.load(Ordering::SeqCst) < stale.len() {
+                match client.try_acquire()? {
+                    Some(token) => handles.push(scope.spawn(move || {
+                        let _token = token;
+                        work()
+                    })),
+                    None => thread::sleep(Duration::from_millis(10)),
+                }
+            }
+
+            let mut first_error = None;
+            for handle in handles {
+                let result = handle
+                    .join()
+                    .map_err(|_| anyhow!("compiler thread panicked"))
+                    .and_then(|result| result);
+                if let Err(err) = result {
+                    first_error.get_or_insert(err);
+                }
+            }
+
+            match first_error {
+                Some(err) => Err(err),
+                None => Ok(()),
+            }
+        })?;
+    }
+
+    Ok(jobs.into_iter().map(|job| job.object).collect())
+}
+
+/// The compilation of one source file.
+struct Job {
+    source: PathBuf,
+    object: PathBuf,
+    depfile: PathBuf,
+    stampfile: PathBuf,
+}
+
+impl Job {
+    fn new(source: &Path, obj_dir: &Path) -> Self {
+        // Distinguish sources with the same name in different
+        // directories.
+        let hash = {
+            let mut hasher = DefaultHasher::new();
+            source.hash(&mut hasher);
+            hasher.finish()
+        };
+        let stem = source
+            .file_stem()
+            .map(|stem| stem.to_string_lossy().into_owned())
+            .unwrap_or_default();
+        let base = obj_dir.join(format!("{stem}-{hash:016x}"));
+
+        Self {
+            source: source.to_owned(),
+            object: base.with_extension("o"),
+            depfile: base.with_extension("d"),
+            stampfile: base.with_extension("stamp"),
+        }
+    }
+
+    fn is_up_to_date(&self, stamp: &str) -> bool {
+        is_up_to_date(&self.object, &self.depfile, &self.stampfile, stamp)
+    }
+
+    /// Complete the compiler command for this source.
+    fn command(&self, mut cmd: Command) -> Command {
+        cmd.arg("-MD")
+            .arg("-MF")
+            .arg(&self.depfile)
+            .arg("-c")
+            .arg(&self.source)
+            .arg("-o")
+            .arg(&self.object);
+        cmd
+    }
+
+    /// Run the command given by [command()](Job::command).
+    fn run(&self, mut cmd: Command, stamp: &str) -> Result<()> {
+        // Invalidate the cache first in case the compiler fails.
+        let _ = fs::remove_file(&self.stampfile);
+
+        let status = cmd
+            .status()
+            .with_context(|| format!("unable to run {cmd:?}"))?;
+        if !status.success() {
+            bail!("failed to compile {}: {cmd:?}", self.source.display());
+        }
+
+        fs::write(&self.stampfile, stamp)?;
+        Ok(())
+    }
+}
+
+/// Check if `output` is newer than every input recorded in `depfile`
+/// and `stampfile` contains `stamp`.
+pub(crate) fn is_up_to_date(output: &Path, depfile: &Path, stampfile: &Path, stamp: &str) -> bool {
+    let Ok(output_mtime) = mtime(output) else {
+        return false;
+    };
+    if fs::read_to_string(stampfile).ok().as_deref() != Some(stamp) {
+        return false;
+    }
+    let Ok(text) = fs::read_to_string(depfile) else {
+        return false;
+    };
+
+    let inputs = parse_depfile(&text);
+    !inputs.is_empty()
+        && inputs.iter().all(|input| match mtime(input) {
+            Ok(input_mtime) => input_mtime <= output_mtime,
+            Err(_) => false,
+        })
+}
+
+/// Print `cargo:rerun-if-changed` for the inputs recorded in depfiles
+/// of a build compiled by [compile_incremental()].
+///
+/// Only files in the package directory or in `includes` are printed.
+/// Files in libtorch, the CUDA or ROCm toolkit, the output directory
+/// and system directories are skipped.
+pub(crate) fn print_cargo_rerun_if_changed_deps(
+    build: &cc::Build,
+    out_dir: &Path,
+    name: &str,
+    includes: &[PathBuf],
+) -> Result<()> {
+    use super::utils::print_cargo_rerun_if_changed;
+
+    let cwd = env::current_dir()?;
+    let normalize = |path: &Path| {
+        let path = cwd.join(path);
+        fs::canonicalize(&path).unwrap_or(path)
+    };
+
+    let roots: Vec<_> = chain!(
+        env::var_os("CARGO_MANIFEST_DIR").map(PathBuf::from),
+        includes.iter().cloned()
+    )
+    .map(|dir| normalize(&dir))
+    .collect();
+    let excludes: Vec<_> = {
+        let libtorch = crate::probe::probe_libtorch()?;
+        chain!(
+            [out_dir.to_owned()],
+            env::var_os("OUT_DIR").map(PathBuf::from),
+            libtorch.include_paths(None)?,
+            libtorch.lib_dir.parent().map(Path::to_owned),
+        )
+        .map(|dir| normalize(&dir))
+        .collect()
+    };
+
+    let obj_dir = out_dir.join("objects").join(name);
+    let inputs: BTreeSet<_> = build
+        .get_files()
+        .filter_map(|src| fs::read_to_string(Job::new(src, &obj_dir).depfile).ok())
+        .flat_map(|text| parse_depfile(&text))
+        .map(|path| normalize(&path))
+        .filter(|path| {
+            roots.iter().any(|root| path.starts_with(root))
+                && !excludes.iter().any(|dir| path.starts_with(dir))
+        })
+        .collect();
+    inputs.iter().for_each(print_cargo_rerun_if_changed);
+
+    Ok(())
+}
+
+/// An entry of `compile_commands.json`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub(crate) struct CompileCommand {
+    directory: PathBuf,
+    file: PathBuf,
+    arguments: Vec<String>,
+    output: PathBuf,
+}
+
+/// Return the compiler invocations run by [compile_incremental()]
+/// for each source in the [cc::Build].
+pub(crate) fn compile_commands(
+    build: &cc::Build,
+    out_dir: &Path,
+    name: &str,
+) -> Result<Vec<CompileCommand>> {
+    let tool = build.try_get_compiler()?;
+    let cwd = env::current_dir()?;
+    let obj_dir = out_dir.join("objects").join(name);
+
+    let commands = build
+        .get_files()
+        .map(|src| {
+            let job = Job::new(src, &obj_dir);
+            let cmd = job.command(tool.to_command());
+            let arguments = chain!([cmd.get_program()], cmd.get_args())
+                .map(|arg| arg.to_string_lossy().into_owned())
+                .collect();
+
+            CompileCommand {
+                directory: cwd.clone(),
+                file: cwd.join(&job.source),
+                arguments,
+                output: job.object,
+            }
+        })
+        .collect();
+    Ok(commands)
+}
+
+/// Write `compile_commands.json` to the directory `dir`.
+///
+/// Entries in an existing file are kept unless the same source is
+/// given in `commands`, so that multiple extensions in one build
+/// script can share the file. An existing file that cannot be parsed,
+/// such as one generated by CMake, is not overwritten.
+pub(crate) fn write_compile_commands(dir: &Path, commands: &[CompileCommand]) -> Result<()> {
+    fs::create_dir_all(dir)
+        .with_context(|| format!("unable to create directory {}", dir.display()))?;
+    let path = dir.join("compile_commands.json");
+
+    let existing: Vec<CompileCommand> = match fs::read_to_string(&path) {
+        Ok(text) => serde_json::from_str(&text).with_context(|| {
+            format!(
+                "unable to parse {}, refusing to overwrite it; remove the file to regenerate it",
+                path.display()
+            )
+        })?,
+        Err(err) if err.kind() == io::ErrorKind::NotFound => vec![],
+        Err(err) => {
+            return Err(err).with_context(|| format!("unable to read {}", path.display()));
+        }
+    };
+    let entries: Vec<_> = existing
+        .into_iter()
+        .filter(|old| !commands.iter().any(|new| new.file == old.file))
+        .chain(commands.iter().cloned())
+        .collect();
+
+    let text = serde_json::to_string_pretty(&entries)?;
+    fs::write(&path, text).with_context(|| format!("unable to write {}", path.display()))?;
+    Ok(())
+}
+
+/// Write `compile_commands.json` for the sources in the [cc::Build]
+/// to `out_dir`, and to `extra_dir` if given.
+pub(crate) fn emit_compile_commands(
+    build: &cc::Build,
+    out_dir: &Path,
+    name: &str,
+    extra_dir: Option<&Path>,
+) -> Result<()> {
+    let commands = compile_commands(build, out_dir, name)?;
+    write_compile_commands(out_dir, &commands)?;
+    if let Some(dir) = extra_dir {
+        write_compile_commands(dir, &commands)?;
+    }
+    Ok(())
+}
+
+/// Archive objects into the static library `name` and print Cargo
+/// instructions to link it.
+pub(crate) fn archive_objects<P>(objects: &[P], name: &str) -> Result<()>
+where
+    P: AsRef<Path>,
+{
+    cc::Build::new()
+        .cpp(true)
+        .objects(objects.iter().map(|path| path.as_ref()))
+        .try_compile(name)?;
+    Ok(())
+}
+
+/// The fingerprint of the probed libtorch. Objects are rebuilt if it
+/// changes.
+pub(crate) fn libtorch_fingerprint() -> Result<String> {
+    let libtorch = crate::probe::probe_libtorch()?;
+    Ok(format!("{libtorch:?}"))
+}
+
+/// Parse the prerequisites in a Makefile-style depfile.
+pub(crate) fn parse_depfile(text: &str) -> Vec<PathBuf> {
+    let mut paths = vec![];
+    let mut current = String::new();
+    let mut in_prerequisites = false;
+    let mut chars = text.chars().peekable();
+
+    let mut finish = |current: &mut String, in_prerequisites: bool| {
+        if in_prerequisites && !current.is_empty() {
+            paths.push(PathBuf::from(&*current));
+        }
+        current.clear();
+    };
+
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' => match chars.peek() {
+                // line continuation
+                Some('\n') => {
+                    chars.next();
+                    finish(&mut current, in_prerequisites);
+                }
+                Some('\r') => {
+                    chars.next();
+                    if chars.peek() == Some(&'\n') {
+                        chars.next();
+                    }
+                    finish(&mut current, in_prerequisites);
+                }
+                // escaped space
+                Some(' ') => {
+                    chars.next();
+                    current.push(' ');
+                }
+                _ => current.push('\\'),
+            },
+            '$' if chars.peek() == Some(&'$') => {
+                chars.next();
+                current.push('$');
+            }
+            ':' if !in_prerequisites
+                && matches!(chars.peek(), Some(' ' | '\t' | '\n' | '\r') | None) =>
+            {
+                current.clear();
+                in_prerequisites = true;
+            }
+            ' ' | '\t' => finish(&mut current, in_prerequisites),
+            '\n' | '\r' => {
+                finish(&mut current, in_prerequisites);
+                // A new rule starts at the next line.
+                in_prerequisites = false;
+            }
+            _ => current.push(c),
+        }
+    }
+    finish(&mut current, in_prerequisites);
+
+    paths
+}
+
+fn mtime(path: &Path) -> Result<SystemTime> {
+    Ok(fs::metadata(path)?.modified()?)
+}
+
+/// Connect to the jobserver of Cargo, or create one limited by the
+/// available parallelism.
+fn jobserver_client() -> Result<jobserver::Client> {
+    // Safety: the file descriptors given by Cargo are not used
+    // elsewhere in build scripts.
+    if let Some(client) = unsafe { jobserver::Client::from_env() } {
+        return Ok(client);
+    }
+
+    // One job runs with the implicit token.
+    let jobs = thread::available_parallelism()
+        .map(NonZeroUsize::get)
+        .unwrap_or(1);
+    Ok(jobserver::Client::new(jobs.saturating_sub(1).max(1))?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{parse_depfile, write_compile_commands, CompileCommand};
+    use std::{fs, path::PathBuf};
+
+    #[test]
+    fn parse_makefile_depfile() {
+        let text =
+            "obj/nms.o: src/nms.cu /usr/include/a\\ b.h \\\n  include/nms.h\n\ninclude/nms.h:\n";
+        let paths = parse_depfile(text);
+        assert_eq!(
+            paths,
+            [
+                PathBuf::from("src/nms.cu"),
+                PathBuf::from("/usr/include/a b.h"),
+                PathBuf::from("include/nms.h"),
+            ]
+        );
+    }
+
+    #[test]
+    fn keep_foreign_compile_commands() {
+        let dir = std::env::temp_dir().join(format!("compile-commands-{}", std::process::id()));
+        let path = dir.join("compile_commands.json");
+        let command = |file: &str| CompileCommand {
+            directory: PathBuf::from("/src"),
+            file: PathBuf::from(file),
+            arguments: vec!["c++".into(), "-c".into(), file.into()],
+            output: PathBuf::from(format!("{file}.o")),
+        };
+
+        // entries of other extensions are merged
+        write_compile_commands(&dir, &[command("/src/a.cpp")]).unwrap();
+        write_compile_commands(&dir, &[command("/src/b.cpp")]).unwrap();
+        let text = fs::read_to_string(&path).unwrap();
+        let entries: Vec<CompileCommand> = serde_json::from_str(&text).unwrap();
+        assert_eq!(entries.len(), 2);
+
+        // a file written by another tool is left intact
+        let foreign = r#"[{"directory": "/src", "file": "a.cpp", "command": "c++ a.cpp"}]"#;
+        fs::write(&path, foreign).unwrap();
+        assert!(write_compile_commands(&dir, &[command("/src/a.cpp")]).is_err());
+        assert_eq!(fs::read_to_string(&path).unwrap(), foreign);
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,165 @@
+use super::{cache::Launcher, incremental::is_up_to_date};
+use anyhow::{bail, ensure, Context as _, Result};
+use std::{
+    collections::hash_map::DefaultHasher,
+    fs,
+    hash::{Hash, Hasher},
+    path::Path,
+};
+
+/// Precompile `header` with the compiler of the [cc::Build] and
+/// include it in every source of the build.
+///
+/// The header is given in the `#include <...>` spelling, for example
+/// `torch/extension.h`. The precompiled header is cached in
+/// `{out_dir}/pch` and rebuilt if the compiler command or the
+/// `fingerprint` changes, or any header recorded in its depfile is
+/// modified. gcc produces a `.gch` file, which is picked
+/// up by `-include`, and clang produces a `.pch` file given by
+/// `-include-pch`. The compiler runs through the compiler cache of the
+/// `launcher` if given.
+pub(crate) fn configure_pch(
+    build: &mut cc::Build,
+    out_dir: &Path,
+    header: &str,
+    fingerprint: &str,
+    launcher: Option<&Launcher>,
+) -> Result<()> {
+    let tool = build.try_get_compiler()?;
+    ensure!(
+        !tool.is_like_msvc(),
+        "precompiled headers are not supported for MSVC-like compilers"
+    );
+
+    // The precompiled header is only valid for the same compiler and
+    // flags.
+    let hash = {
+        let mut hasher = DefaultHasher::new();
+        tool.path().hash(&mut hasher);
+        tool.args().hash(&mut hasher);
+        header.hash(&mut hasher);
+        fingerprint.hash(&mut hasher);
+        hasher.finish()
+    };
+    let pch_dir = out_dir.join("pch").join(format!("{hash:016x}"));
+    fs::create_dir_all(&pch_dir)
+        .with_context(|| format!("unable to create directory {}", pch_dir.display()))?;
+
+    // The stub header includes the requested header. If the
+    // precompiled header is rejected by the compiler, the stub is
+    // parsed instead.
+    let stub = pch_dir.join("pch.h");
+    let pch = if tool.is_like_clang() {
+        pch_dir.join("pch.h.pch")
+    } else {
+        pch_dir.join("pch.h.gch")
+    };
+
+    let depfile = pch_dir.join("pch.h.d");
+    let stampfile = pch_dir.join("pch.h.stamp");
+    let stamp = format!("{hash:016x}");
+
+    if !is_up_to_date(&pch, &depfile, &stampfile, &stamp) {
+        // Invalidate the cache first in case the compiler fails.
+        let _ = fs::remove_file(&stampfile);
+
+        // Rewriting the stub also marks objects that include it as
+        // stale.
+        fs::write(&stub, format!("#include <{header}>\n"))?;
+
+        let mut cmd = tool.to_command();
+        cmd.arg("-x")
+            .arg("c++-header")
+            .arg("-MD")
+            .arg("-MF")
+            .arg(&depfile)
+            .arg(&stub)
+            .arg("-o")
+            .arg(&pch);
+        if let Some(launcher) = launcher {
+            cmd = launcher.wrap(cmd)?;
+        }
+        let status = cmd
+            .status()
+            .with_context(|| format!("unable to run {cmd:?}"))?;
+        if !status.success() {
+            let _ = fs::remove_file(&pch);
+            bail!("failed to precompile header {header}: {cmd:?}");
+        }
+        fs::write(&stampfile, &stamp)?;
+    }
+
+    if tool.is_like_clang() {
+        build.flag("-include-pch").flag(&pch);
+    } else {
+        build.flag("-Winvalid-pch").flag("-include").flag(&stub);
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::configure_pch;
+    use std::{
+        fs,
+        time::{Duration, SystemTime},
+    };
+
+    // needs a native gcc or clang for the hard-coded target
+    #[test]
+    #[cfg(all(target_os = "linux", target_arch = "x86_64", target_env = "gnu"))]
+    fn rebuild_pch_on_header_change() {
+        let dir = std::env::temp_dir().join(format!("pch-{}", std::process::id()));
+        let include_dir = dir.join("include");
+        fs::create_dir_all(&include_dir).unwrap();
+        let header = include_dir.join("common.h");
+        fs::write(&header, "inline int answer() { return 42; }\n").unwrap();
+
+        let mut build = cc::Build::new();
+        build
+            .cpp(true)
+            .cargo_metadata(false)
+            .target("x86_64-unknown-linux-gnu")
+            .host("x86_64-unknown-linux-gnu")
+            .opt_level(0)
+            .include(&include_dir);
+        let configure = || {
+            let mut build = build.clone();
+            configure_pch(&mut build, &dir, "common.h", "fingerprint", None).unwrap();
+        };
+        let pch_mtime = || {
+            let pch_dir = fs::read_dir(dir.join("pch"))
+                .unwrap()
+                .next()
+                .unwrap()
+                .unwrap();
+            let pch = fs::read_dir(pch_dir.path())
+                .unwrap()
+                .map(|entry| entry.unwrap().path())
+                .find(|path| path.to_string_lossy().ends_with("ch"))
+                .unwrap();
+            fs::metadata(pch).unwrap().modified().unwrap()
+        };
+
+        configure();
+        let first = pch_mtime();
+
+        // up to date
+        configure();
+        assert_eq!(pch_mtime(), first);
+
+        // a header newer than the precompiled header triggers a rebuild
+        let later = SystemTime::now() + Duration::from_secs(10);
+        fs::File::options()
+            .write(true)
+            .open(&header)
+            .unwrap()
+            .set_modified(later)
+            .unwrap();
+        configure();
+        assert!(pch_mtime() > first);
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,341 @@
+use crate::{
+    probe::probe_python_extension, CompilerCache, CudaArchPolicy, RpathPolicy, TorchExtension,
+};
+use anyhow::{anyhow, ensure, Context as _, Result};
+use cfg_if::cfg_if;
+use std::{
+    env, fs,
+    path::{Path, PathBuf},
+};
+
+/// Build C++ and CUDA sources into a Python extension module.
+///
+/// Sources are compiled as [TorchExtension] does, with
+/// `TORCH_EXTENSION_NAME` set to the module name and the pybind11 ABI
+/// macros that PyTorch is built with, so that the module can be
+/// defined by `PYBIND11_MODULE(TORCH_EXTENSION_NAME, m)`. The objects
+/// are linked into a shared object named after the `EXT_SUFFIX` of
+/// the Python interpreter and placed in the
+/// [module_dir()](PythonExtension::module_dir), ready for `import`.
+///
+/// The module links to libtorch and `torch_python`, but not to
+/// libpython. Python symbols are resolved by the interpreter that
+/// loads the module. libtorch must come from a PyTorch installation,
+/// for example by setting `LIBTORCH_USE_PYTORCH`.
+#[derive(Debug, Clone)]
+pub struct PythonExtension {
+    torch: TorchExtension,
+    module_dir: Option<PathBuf>,
+}
+
+impl PythonExtension {
+    pub fn new() -> Self {
+        Self {
+            torch: TorchExtension::new(),
+            module_dir: None,
+        }
+    }
+
+    /// Set the directory to place the built module. It defaults to
+    /// `OUT_DIR`.
+    pub fn module_dir<P>(&mut self, dir: P) -> &mut Self
+    where
+        P: AsRef<Path>,
+    {
+        self.module_dir = Some(dir.as_ref().to_owned());
+        self
+    }
+
+    /// See [TorchExtension::force_cuda_link()].
+    pub fn force_cuda_link(&mut self, enabled: bool) -> &mut Self {
+        self.torch.force_cuda_link(enabled);
+        self
+    }
+
+    /// Set the policy to embed rpath into the module. It defaults to
+    /// [RpathPolicy::Absolute].
+    pub fn rpath_policy(&mut self, policy: RpathPolicy) -> &mut Self {
+        self.torch.rpath_policy(policy);
+        self
+    }
+
+    /// See [TorchExtension::extra_rpath()].
+    pub fn extra_rpath<S>(&mut self, entry: S) -> &mut Self
+    where
+        S: AsRef<str>,
+    {
+        self.torch.extra_rpath(entry);
+        self
+    }
+
+    pub fn extra_rpaths<S>(&mut self, entries: S) -> &mut Self
+    where
+        S: IntoIterator,
+        S::Item: AsRef<str>,
+    {
+        self.torch.extra_rpaths(entries);
+        self
+    }
+
+    /// See [TorchExtension::compiler_cache()].
+    pub fn compiler_cache(&mut self, cache: CompilerCache) -> &mut Self {
+        self.torch.compiler_cache(cache);
+        self
+    }
+
+    /// See [TorchExtension::compiler_cache_base_dir()].
+    pub fn compiler_cache_base_dir<P>(&mut self, dir: P) -> &mut Self
+    where
+        P: AsRef<Path>,
+    {
+        self.torch.compiler_cache_base_dir(dir);
+        self
+    }
+
+    /// See [TorchExtension::cuda_arch_policy()].
+    pub fn cuda_arch_policy(&mut self, policy: CudaArchPolicy) -> &mut Self {
+        self.torch.cuda_arch_policy(policy);
+        self
+    }
+
+    /// See [TorchExtension::ptx_only()].
+    pub fn ptx_only(&mut self, enabled: bool) -> &mut Self {
+        self.torch.ptx_only(enabled);
+        self
+    }
+
+    /// See [TorchExtension::compress_fatbin()].
+    pub fn compress_fatbin(&mut self, enabled: bool) -> &mut Self {
+        self.torch.compress_fatbin(enabled);
+        self
+    }
+
+    /// See [TorchExtension::cxx_flag()].
+    pub fn cxx_flag<S>(&mut self, flag: S) -> &mut Self
+    where
+        S: AsRef<str>,
+    {
+        self.torch.cxx_flag(flag);
+        self
+    }
+
+    pub fn cxx_flags<S>(&mut self, flags: S) -> &mut Self
+    where
+        S: IntoIterator,
+        S::Item: AsRef<str>,
+    {
+        self.torch.cxx_flags(flags);
+        self
+    }
+
+    /// See [TorchExtension::nvcc_flag()].
+    pub fn nvcc_flag<S>(&mut self, flag: S) -> &mut Self
+    where
+        S: AsRef<str>,
+    {
+        self.torch.nvcc_flag(flag);
+        self
+    }
+
+    pub fn nvcc_flags<S>(&mut self, flags: S) -> &mut Self
+    where
+        S: IntoIterator,
+        S::Item: AsRef<str>,
+    {
+        self.torch.nvcc_flags(flags);
+        self
+    }
+
+    /// See [TorchExtension::rdc()].
+    pub fn rdc(&mut self, enabled: bool) -> &mut Self {
+        self.torch.rdc(enabled);
+        self
+    }
+
+    /// See [TorchExtension::precompiled_header()].
+    pub fn precompiled_header<S>(&mut self, header: S) -> &mut Self
+    where
+        S: AsRef<str>,
+    {
+        self.torch.precompiled_header(header);
+        self
+    }
+
+    pub fn include<P>(&mut self, path: P) -> &mut Self
+    where
+        P: AsRef<Path>,
+    {
+        self.torch.include(path);
+        self
+    }
+
+    pub fn includes<P>(&mut self, paths: P) -> &mut Self
+    where
+        P: IntoIterator,
+        P::Item: AsRef<Path>,
+    {
+        self.torch.includes(paths);
+        self
+    }
+
+    pub fn header<P>(&mut self, path: P) -> &mut Self
+    where
+        P: AsRef<Path>,
+    {
+        self.torch.header(path);
+        self
+    }
+
+    pub fn headers<P>(&mut self, paths: P) -> &mut Self
+    where
+        P: IntoIterator,
+        P::Item: AsRef<Path>,
+    {
+        self.torch.headers(paths);
+        self
+    }
+
+    pub fn source<P>(&mut self, path: P) -> &mut Self
+    where
+        P: AsRef<Path>,
+    {
+        self.torch.source(path);
+        self
+    }
+
+    pub fn sources<P>(&mut self, paths: P) -> &mut Self
+    where
+        P: IntoIterator,
+        P::Item: AsRef<Path>,
+    {
+        self.torch.sources(paths);
+        self
+    }
+
+    pub fn link_search<P>(&mut self, path: P) -> &mut Self
+    where
+        P: AsRef<Path>,
+    {
+        self.torch.link_search(path);
+        self
+    }
+
+    pub fn link_searches<P>(&mut self, paths: P) -> &mut Self
+    where
+        P: IntoIterator,
+        P::Item: AsRef<Path>,
+    {
+        self.torch.link_searches(paths);
+        self
+    }
+
+    pub fn library<P>(&mut self, name: P) -> &mut Self
+    where
+        P: AsRef<str>,
+    {
+        self.torch.library(name);
+        self
+    }
+
+    pub fn libraries<P>(&mut self, names: P) -> &mut Self
+    where
+        P: IntoIterator,
+        P::Item: AsRef<str>,
+    {
+        self.torch.libraries(names);
+        self
+    }
+
+    /// Get the directory to place the built module.
+    pub fn out_dir(&self) -> Result<PathBuf> {
+        Ok(match &self.module_dir {
+            Some(dir) => dir.clone(),
+            None => {
+                let out_dir = env::var_os("OUT_DIR")
+                    .ok_or_else(|| anyhow!("OUT_DIR environment variable is not set"))?;
+                PathBuf::from(out_dir)
+            }
+        })
+    }
+
+    /// Compile C++ and GPU source code into the Python module `name`
+    /// and return the path to the module file.
+    pub fn build(&self, name: &str) -> Result<PathBuf> {
+        ensure!(
+            is_python_identifier(name),
+            "'{name}' is not a valid Python module name"
+        );
+
+        let libtorch = crate::probe::probe_libtorch()?;
+        let torch_python = {
+            cfg_if! {
+                if #[cfg(target_os = "macos")] {
+                    libtorch.lib_dir.join("libtorch_python.dylib")
+                } else {
+                    libtorch.lib_dir.join("libtorch_python.so")
+                }
+            }
+        };
+        ensure!(
+            torch_python.exists(),
+            "{} does not exist, PythonExtension requires libtorch from a PyTorch installation",
+            torch_python.display()
+        );
+
+        let python = probe_python_extension()?;
+        let module_dir = self.out_dir()?;
+        fs::create_dir_all(&module_dir)
+            .with_context(|| format!("unable to create {}", module_dir.display()))?;
+
+        let objects = self.torch.compile_objects(name, |build| {
+            build
+                .include(&python.include)
+                .define("TORCH_EXTENSION_NAME", name)
+                .define("TORCH_API_INCLUDE_EXTENSION_H", None);
+            python.pybind11_abi.iter().for_each(|(key, value)| {
+                build.define(key, format!("\"{value}\"").as_str());
+            });
+        })?;
+
+        // Python symbols are provided by the interpreter.
+        let mut link_args = vec!["-ltorch_python".to_string()];
+        if cfg!(target_os = "macos") {
+            link_args.push("-Wl,-undefined,dynamic_lookup".to_string());
+        }
+
+        let module = module_dir.join(format!("{name}{}", python.ext_suffix));
+        self.torch.link_shared(&objects, &module, &link_args)?;
+        Ok(module)
+    }
+}
+
+impl Default for PythonExtension {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+fn is_python_identifier(name: &str) -> bool {
+    let mut chars = name.chars();
+    match chars.next() {
+        Some(c) if c == '_' || c.is_ascii_alphabetic() => {
+            chars.all(|c| c == '_' || c.is_ascii_alphanumeric())
+        }
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn python_module_names() {
+        assert!(is_python_identifier("my_ops"));
+        assert!(is_python_identifier("_C"));
+        assert!(!is_python_identifier(""));
+        assert!(!is_python_identifier("2ops"));
+        assert!(!is_python_identifier("my-ops"));
+        assert!(!is_python_identifier("my.ops"));
+    }
+}
@@ -0,0 +1,189 @@
+use cfg_if::cfg_if;
+use indexmap::IndexSet;
+use std::path::{Path, PathBuf};
+
+/// The policy to embed runtime library search paths (rpath) into
+/// linked artifacts.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Default)]
+pub enum RpathPolicy {
+    /// Embed the absolute path of each library directory.
+    #[default]
+    Absolute,
+    /// Embed a single path relative to the artifact location, that is
+    /// `$ORIGIN/<dir>` on Linux and `@loader_path/<dir>` on macOS.
+    OriginRelative(PathBuf),
+    /// Do not embed any rpath.
+    None,
+}
+
+impl RpathPolicy {
+    /// Create an [OriginRelative](RpathPolicy::OriginRelative) policy.
+    pub fn origin_relative<P>(dir: P) -> Self
+    where
+        P: AsRef<Path>,
+    {
+        Self::OriginRelative(dir.as_ref().to_owned())
+    }
+
+    /// Generate the rpath entries for the library directories.
+    ///
+    /// Duplicated entries are removed while the order is preserved.
+    pub fn rpath_entries<I>(&self, lib_dirs: I) -> Vec<String>
+    where
+        I: IntoIterator,
+        I::Item: AsRef<Path>,
+    {
+        let entries: IndexSet<String> = match self {
+            Self::Absolute => lib_dirs
+                .into_iter()
+                .map(|dir| dir.as_ref().display().to_string())
+                .collect(),
+            Self::OriginRelative(dir) => {
+                cfg_if! {
+                    if #[cfg(target_os = "macos")] {
+                        let origin = "@loader_path";
+                    } else {
+                        let origin = "$ORIGIN";
+                    }
+                }
+
+                let entry = if dir.as_os_str().is_empty() {
+                    origin.to_string()
+                } else {
+                    format!("{origin}/{}", dir.display())
+                };
+                [entry].into_iter().collect()
+            }
+            Self::None => IndexSet::new(),
+        };
+
+        entries.into_iter().collect()
+    }
+}
+
+/// The kind of Cargo targets that linker arguments apply to.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum LinkTarget {
+    /// All targets, using `cargo:rustc-link-arg`.
+    All,
+    /// Binaries, using `cargo:rustc-link-arg-bins`.
+    Bins,
+    /// Tests, using `cargo:rustc-link-arg-tests`.
+    Tests,
+    /// Examples, using `cargo:rustc-link-arg-examples`.
+    Examples,
+    /// Benchmarks, using `cargo:rustc-link-arg-benches`.
+    Benches,
+    /// The `cdylib` library, using `cargo:rustc-cdylib-link-arg`.
+    Cdylib,
+}
+
+impl LinkTarget {
+    /// Get the Cargo build script instruction for this target.
+    pub fn cargo_instruction(&self) -> &'static str {
+        match self {
+            Self::All => "rustc-link-arg",
+            Self::Bins => "rustc-link-arg-bins",
+            Self::Tests => "rustc-link-arg-tests",
+            Self::Examples => "rustc-link-arg-examples",
+            Self::Benches => "rustc-link-arg-benches",
+            Self::Cdylib => "rustc-cdylib-link-arg",
+        }
+    }
+
+    /// Print the linker argument for this target.
+    pub fn print_cargo_link_arg(&self, arg: &str) {
+        println!("cargo:{}={arg}", self.cargo_instruction());
+    }
+}
+
+/// Generate the rpath entries for the library directories by the
+/// policy, followed by the `extra` entries verbatim.
+///
+/// Duplicated entries are removed while the order is preserved.
+pub(crate) fn rpath_entries_with_extra<I>(
+    policy: &RpathPolicy,
+    extra: &[String],
+    lib_dirs: I,
+) -> Vec<String>
+where
+    I: IntoIterator,
+    I::Item: AsRef<Path>,
+{
+    let entries: IndexSet<String> = policy
+        .rpath_entries(lib_dirs)
+        .into_iter()
+        .chain(extra.iter().cloned())
+        .collect();
+    entries.into_iter().collect()
+}
+
+/// Print rpath linker arguments for the library directories and the
+/// `extra` entries.
+///
+/// If `targets` is empty, the arguments apply to all targets.
+pub(crate) fn print_cargo_rpaths<I>(
+    policy: &RpathPolicy,
+    extra: &[String],
+    targets: &[LinkTarget],
+    lib_dirs: I,
+) where
+    I: IntoIterator,
+    I::Item: AsRef<Path>,
+{
+    let targets = if targets.is_empty() {
+        &[LinkTarget::All]
+    } else {
+        targets
+    };
+
+    for entry in rpath_entries_with_extra(policy, extra, lib_dirs) {
+        for target in targets {
+            target.print_cargo_link_arg(&format!("-Wl,-rpath,{entry}"));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{rpath_entries_with_extra, RpathPolicy};
+
+    #[test]
+    fn rpath_entries() {
+        let dirs = [
+            "/opt/libtorch/lib",
+            "/usr/local/cuda/lib64",
+            "/opt/libtorch/lib",
+        ];
+
+        assert_eq!(
+            RpathPolicy::Absolute.rpath_entries(dirs),
+            ["/opt/libtorch/lib", "/usr/local/cuda/lib64"]
+        );
+        assert!(RpathPolicy::None.rpath_entries(dirs).is_empty());
+
+        #[cfg(target_os = "linux")]
+        assert_eq!(
+            RpathPolicy::origin_relative("../lib").rpath_entries(dirs),
+            ["$ORIGIN/../lib"]
+        );
+    }
+
+    #[test]
+    fn extra_rpath_entries() {
+        let dirs = ["/opt/libtorch/lib"];
+        let extra = [
+            "/opt/vendor/lib".to_string(),
+            "/opt/libtorch/lib".to_string(),
+        ];
+
+        assert_eq!(
+            rpath_entries_with_extra(&RpathPolicy::Absolute, &extra, dirs),
+            ["/opt/libtorch/lib", "/opt/vendor/lib"]
+        );
+        assert_eq!(
+            rpath_entries_with_extra(&RpathPolicy::None, &extra, dirs),
+            ["/opt/vendor/lib", "/opt/libtorch/lib"]
+        );
+    }
+}
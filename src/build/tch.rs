@@ -0,0 +1,465 @@
+use super::{
+    cache::Launcher,
+    incremental::{archive_objects, compile_incremental_with, libtorch_fingerprint},
+    utils::print_cargo_rerun_if_changed,
+};
+use crate::{
+    env::LIBTCH_DIR,
+    libtch_gen::{generate_libtch_ops, libtorch_declarations, read_op_list, OpFilter},
+    CompilerCache, CppExtension, LinkTarget, RpathPolicy,
+};
+use anyhow::{anyhow, Context as _, Result};
+use std::{
+    env, fs,
+    path::{Path, PathBuf},
+};
+
+/// Build the bundled libtch, the C API of libtorch used by the tch
+/// crate.
+///
+/// It compiles `torch_api.cpp` and the generated op wrappers against
+/// the probed libtorch into a static library, generates Rust
+/// bindings for `torch_api.h` and `torch_api_generated.h`, and
+/// prints Cargo instructions to link libtorch.
+///
+/// libtorch CUDA libraries are force-linked if CUDA is available. The
+/// crate should call `keep_cuda_dependency()` from the generated
+/// `cuda_dependency.rs`, see [CppExtension::force_cuda_link()].
+///
+/// The bundled op wrappers match the libtorch version of this crate.
+/// For other libtorch versions, enable
+/// [generate_ops()](TchApi::generate_ops) to regenerate them from the
+/// `Declarations.yaml` of the probed libtorch. To shorten the build
+/// and shrink binaries, the wrappers can be limited to the ops in use
+/// by [allow_ops()](TchApi::allow_ops) and similar methods, and the
+/// image I/O functions can be dropped by
+/// [image_io()](TchApi::image_io).
+#[derive(Debug, Clone)]
+pub struct TchApi {
+    link_python: bool,
+    rpath_policy: RpathPolicy,
+    extra_rpaths: Vec<String>,
+    rpath_targets: Vec<LinkTarget>,
+    compiler_cache: Option<CompilerCache>,
+    compiler_cache_base_dir: Option<PathBuf>,
+    #[cfg(feature = "bindgen")]
+    bindings: bool,
+    generate_ops: bool,
+    declarations: Option<PathBuf>,
+    op_filter: OpFilter,
+    allow_op_files: Vec<PathBuf>,
+    deny_op_files: Vec<PathBuf>,
+    image_io: bool,
+    out_dir: Option<PathBuf>,
+}
+
+impl TchApi {
+    pub fn new() -> Self {
+        Self {
+            link_python: false,
+            rpath_policy: RpathPolicy::Absolute,
+            extra_rpaths: vec![],
+            rpath_targets: vec![],
+            compiler_cache: None,
+            compiler_cache_base_dir: None,
+            #[cfg(feature = "bindgen")]
+            bindings: true,
+            generate_ops: false,
+            declarations: None,
+            op_filter: OpFilter::new(),
+            allow_op_files: vec![],
+            deny_op_files: vec![],
+            image_io: true,
+            out_dir: None,
+        }
+    }
+
+    pub fn out_dir(&self) -> Result<PathBuf> {
+        Ok(match &self.out_dir {
+            Some(dir) => dir.clone(),
+            None => {
+                let out_dir = env::var_os("OUT_DIR")
+                    .ok_or_else(|| anyhow!("OUT_DIR environment variable is not set"))?;
+                PathBuf::from(out_dir)
+            }
+        })
+    }
+
+    /// Write the library, the bindings and the regenerated sources to
+    /// `dir` instead of `OUT_DIR`. See [out_dir()](TchApi::out_dir).
+    pub fn output_dir<P>(&mut self, dir: P) -> &mut Self
+    where
+        P: AsRef<Path>,
+    {
+        self.out_dir = Some(dir.as_ref().to_owned());
+        self
+    }
+
+    pub fn link_python(&mut self, enabled: bool) -> &mut Self {
+        self.link_python = enabled;
+        self
+    }
+
+    /// Set the policy to embed rpath into linked artifacts. See
+    /// [CppExtension::rpath_policy()].
+    pub fn rpath_policy(&mut self, policy: RpathPolicy) -> &mut Self {
+        self.rpath_policy = policy;
+        self
+    }
+
+    /// Add an rpath entry that is embedded verbatim in addition to
+    /// the entries generated by the
+    /// [rpath_policy()](TchApi::rpath_policy).
+    pub fn extra_rpath<S>(&mut self, entry: S) -> &mut Self
+    where
+        S: AsRef<str>,
+    {
+        self.extra_rpaths.push(entry.as_ref().to_owned());
+        self
+    }
+
+    pub fn extra_rpaths<S>(&mut self, entries: S) -> &mut Self
+    where
+        S: IntoIterator,
+        S::Item: AsRef<str>,
+    {
+        self.extra_rpaths
+            .extend(entries.into_iter().map(|s| s.as_ref().to_owned()));
+        self
+    }
+
+    pub fn rpath_target(&mut self, target: LinkTarget) -> &mut Self {
+        self.rpath_targets.push(target);
+        self
+    }
+
+    pub fn rpath_targets<T>(&mut self, targets: T) -> &mut Self
+    where
+        T: IntoIterator<Item = LinkTarget>,
+    {
+        self.rpath_targets.extend(targets);
+        self
+    }
+
+    /// Run the compiler through a compiler cache. See
+    /// [CppExtension::compiler_cache()].
+    pub fn compiler_cache(&mut self, cache: CompilerCache) -> &mut Self {
+        self.compiler_cache = Some(cache);
+        self
+    }
+
+    /// See [CppExtension::compiler_cache_base_dir()].
+    pub fn compiler_cache_base_dir<P>(&mut self, dir: P) -> &mut Self
+    where
+        P: AsRef<Path>,
+    {
+        self.compiler_cache_base_dir = Some(dir.as_ref().to_owned());
+        self
+    }
+
+    /// Generate Rust bindings in [build()](TchApi::build). It is
+    /// enabled by default.
+    #[cfg(feature = "bindgen")]
+    pub fn bindings(&mut self, enabled: bool) -> &mut Self {
+        self.bindings = enabled;
+        self
+    }
+
+    /// Regenerate the op wrappers from the `Declarations.yaml` of the
+    /// probed libtorch instead of using the bundled ones.
+    ///
+    /// libtch is copied to `{out_dir}/libtch` together with the
+    /// generated `torch_api_generated.cpp.h`, `torch_api_generated.h`
+    /// and `torch_api_generated.rs`. The last one declares the op
+    /// wrappers in Rust and can be used without bindgen.
+    pub fn generate_ops(&mut self, enabled: bool) -> &mut Self {
+        self.generate_ops = enabled;
+        self
+    }
+
+    /// Regenerate the op wrappers from the given `Declarations.yaml`.
+    /// It implies [generate_ops()](TchApi::generate_ops).
+    pub fn declarations<P>(&mut self, path: P) -> &mut Self
+    where
+        P: AsRef<Path>,
+    {
+        self.generate_ops = true;
+        self.declarations = Some(path.as_ref().to_owned());
+        self
+    }
+
+    /// Only wrap the given ATen op, for example `add`, `add_` or
+    /// `add_out`. All overloads of the op are wrapped. Selecting ops
+    /// implies [generate_ops()](TchApi::generate_ops).
+    pub fn allow_op<S>(&mut self, name: S) -> &mut Self
+    where
+        S: AsRef<str>,
+    {
+        self.op_filter.allow_op(name);
+        self
+    }
+
+    /// See [allow_op()](TchApi::allow_op).
+    pub fn allow_ops<S>(&mut self, names: S) -> &mut Self
+    where
+        S: IntoIterator,
+        S::Item: AsRef<str>,
+    {
+        self.op_filter.allow_ops(names);
+        self
+    }
+
+    /// Allow the ops listed in a file, one per line. Blank lines and
+    /// text after `#` are ignored.
+    pub fn allow_ops_file<P>(&mut self, path: P) -> &mut Self
+    where
+        P: AsRef<Path>,
+    {
+        self.allow_op_files.push(path.as_ref().to_owned());
+        self
+    }
+
+    /// Do not wrap the given ATen op. It implies
+    /// [generate_ops()](TchApi::generate_ops).
+    pub fn deny_op<S>(&mut self, name: S) -> &mut Self
+    where
+        S: AsRef<str>,
+    {
+        self.op_filter.deny_op(name);
+        self
+    }
+
+    /// See [deny_op()](TchApi::deny_op).
+    pub fn deny_ops<S>(&mut self, names: S) -> &mut Self
+    where
+        S: IntoIterator,
+        S::Item: AsRef<str>,
+    {
+        self.op_filter.deny_ops(names);
+        self
+    }
+
+    /// Deny the ops listed in a file. See
+    /// [allow_ops_file()](TchApi::allow_ops_file) for the format.
+    pub fn deny_ops_file<P>(&mut self, path: P) -> &mut Self
+    where
+        P: AsRef<Path>,
+    {
+        self.deny_op_files.push(path.as_ref().to_owned());
+        self
+    }
+
+    /// Compile the image I/O functions `at_load_image()`,
+    /// `at_load_image_from_memory()`, `at_save_image()` and
+    /// `at_resize_image()` along with the bundled stb headers. It is
+    /// enabled by default.
+    pub fn image_io(&mut self, enabled: bool) -> &mut Self {
+        self.image_io = enabled;
+        self
+    }
+
+    /// Compile libtch into the static library `name`, write the
+    /// bindings to `{out_dir}/{name}.rs` and link libtorch.
+    pub fn build(&self, name: &str) -> Result<()> {
+        let out_dir = self.out_dir()?;
+        let src_dir = self.prepare_sources(&out_dir)?;
+        let ext = self.cpp_extension(&src_dir);
+
+        let mut build = cc::Build::new();
+        ext.configure_cc(&mut build)?;
+        // libtorch 2 requires C++17. The flag overrides the standard
+        // set by the extension.
+        build.flag("-std=c++17").warnings(false);
+        if !self.image_io {
+            build.define("TCH_NO_IMAGE", None);
+        }
+
+        let launcher = self.compiler_cache.clone().map(|cache| Launcher {
+            cache,
+            base_dir: self.compiler_cache_base_dir.clone(),
+        });
+        let objects = compile_incremental_with(
+            &build,
+            &out_dir,
+            name,
+            &libtorch_fingerprint()?,
+            launcher.as_ref(),
+        )?;
+        archive_objects(&objects, name)?;
+
+        #[cfg(feature = "bindgen")]
+        if self.bindings {
+            let bindings = self
+                .configure_bindgen_in(bindgen::Builder::default(), &src_dir)
+                .generate()?;
+            bindings.write_to_file(out_dir.join(format!("{name}.rs")))?;
+        }
+
+        ext.link()?;
+        Ok(())
+    }
+
+    /// Configure the [bindgen::Builder] to generate bindings for the
+    /// libtch C API. The headers are parsed as C, in which tensors and
+    /// other handles are opaque pointers.
+    ///
+    /// The bundled headers are used. The regenerated headers are only
+    /// picked up by [build()](TchApi::build).
+    #[cfg(feature = "bindgen")]
+    pub fn configure_bindgen(&self, builder: bindgen::Builder) -> bindgen::Builder {
+        self.configure_bindgen_in(builder, Path::new(LIBTCH_DIR))
+    }
+
+    #[cfg(feature = "bindgen")]
+    fn configure_bindgen_in(&self, builder: bindgen::Builder, src_dir: &Path) -> bindgen::Builder {
+        let builder = builder
+            .header(src_dir.join("torch_api.h").display().to_string())
+            .allowlist_file(".*torch_api(_generated)?\\.h");
+        if self.image_io {
+            builder
+        } else {
+            builder.clang_arg("-DTCH_NO_IMAGE")
+        }
+    }
+
+    /// Return the directory of libtch sources to compile. If op
+    /// generation is enabled, libtch is copied to `{out_dir}/libtch`
+    /// and the op wrappers are regenerated there.
+    fn prepare_sources(&self, out_dir: &Path) -> Result<PathBuf> {
+        let mut filter = self.op_filter.clone();
+        for path in &self.allow_op_files {
+            print_cargo_rerun_if_changed(path);
+            filter.allow_ops(read_op_list(path)?);
+        }
+        for path in &self.deny_op_files {
+            print_cargo_rerun_if_changed(path);
+            filter.deny_ops(read_op_list(path)?);
+        }
+
+        if !self.generate_ops && filter.is_empty() {
+            return Ok(PathBuf::from(LIBTCH_DIR));
+        }
+
+        let declarations = match &self.declarations {
+            Some(path) => path.clone(),
+            None => libtorch_declarations()?,
+        };
+        print_cargo_rerun_if_changed(&declarations);
+
+        let src_dir = out_dir.join("libtch");
+        fs::create_dir_all(&src_dir)?;
+
+        for entry in fs::read_dir(LIBTCH_DIR)? {
+            let entry = entry?;
+            let file_name = entry.file_name();
+            let Some(file_name) = file_name.to_str() else {
+                continue;
+            };
+            let is_source = file_name == "torch_api.cpp"
+                || (file_name.ends_with(".h") && !file_name.starts_with("torch_api_generated"));
+            if !is_source {
+                continue;
+            }
+
+            let src = entry.path();
+            let dst = src_dir.join(file_name);
+
+            // The stb headers are only needed by the image I/O
+            // functions.
+            if !self.image_io && file_name.starts_with("stb_") {
+                if dst.exists() {
+                    fs::remove_file(&dst)?;
+                }
+                continue;
+            }
+
+            // Keep the modification time of unchanged copies for
+            // incremental compilation.
+            let text = fs::read(&src)?;
+            if fs::read(&dst).ok().as_deref() != Some(text.as_slice()) {
+                fs::write(&dst, text)
+                    .with_context(|| format!("unable to write {}", dst.display()))?;
+            }
+        }
+
+        generate_libtch_ops(&declarations, &src_dir, &filter)?;
+        Ok(src_dir)
+    }
+
+    /// Create a [CppExtension] that compiles libtch in `src_dir`.
+    fn cpp_extension(&self, src_dir: &Path) -> CppExtension {
+        let mut ext = CppExtension::new();
+        ext.link_python(self.link_python)
+            .force_cuda_link(true)
+            .rpath_policy(self.rpath_policy.clone())
+            .extra_rpaths(&self.extra_rpaths)
+            .rpath_targets(self.rpath_targets.iter().copied())
+            .include(src_dir)
+            .source(src_dir.join("torch_api.cpp"));
+        ext
+    }
+}
+
+impl Default for TchApi {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::TchApi;
+    use crate::env::LIBTCH_DIR;
+    use std::{fs, path::PathBuf};
+
+    #[test]
+    fn tch_api_builder() {
+        let dir = std::env::temp_dir().join(format!("tch-api-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let declarations = dir.join("Declarations.yaml");
+        fs::write(
+            &declarations,
+            r#"
+- name: zeros
+  operator_name: zeros
+  overload_name: ''
+  method_of: [Type, namespace]
+  arguments:
+  - {name: size, dynamic_type: at::IntArrayRef, is_nullable: false}
+  returns:
+  - {dynamic_type: at::Tensor}
+- name: ones
+  operator_name: ones
+  overload_name: ''
+  method_of: [Type, namespace]
+  arguments:
+  - {name: size, dynamic_type: at::IntArrayRef, is_nullable: false}
+  returns:
+  - {dynamic_type: at::Tensor}
+"#,
+        )
+        .unwrap();
+
+        // the bundled sources are used as is by default
+        let api = TchApi::new();
+        let src_dir = api.prepare_sources(&dir).unwrap();
+        assert_eq!(src_dir, PathBuf::from(LIBTCH_DIR));
+
+        let mut api = TchApi::new();
+        api.output_dir(&dir)
+            .declarations(&declarations)
+            .allow_op("zeros")
+            .image_io(false);
+        assert_eq!(api.out_dir().unwrap(), dir);
+
+        let src_dir = api.prepare_sources(&api.out_dir().unwrap()).unwrap();
+        assert_eq!(src_dir, dir.join("libtch"));
+        assert!(src_dir.join("torch_api.cpp").exists());
+        assert!(!src_dir.join("stb_image.h").exists());
+        let header = fs::read_to_string(src_dir.join("torch_api_generated.h")).unwrap();
+        assert!(header.contains("atg_zeros("));
+        assert!(!header.contains("atg_ones("));
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,725 @@
+use super::{
+    cache::Launcher,
+    incremental::{
+        archive_objects, compile_incremental_with, emit_compile_commands, libtorch_fingerprint,
+        print_cargo_rerun_if_changed_deps,
+    },
+    pch::configure_pch,
+    utils::print_cargo_rerun_if_changed,
+};
+use crate::{
+    CompilerCache, CppExtension, CudaArchPolicy, CudaExtension, HipExtension, LinkTarget,
+    RpathPolicy,
+};
+use anyhow::{ensure, Context as _, Result};
+use cfg_if::cfg_if;
+use itertools::chain;
+use log::warn;
+use std::path::{Path, PathBuf};
+
+/// The file extensions of GPU source files.
+const GPU_SOURCE_EXTENSIONS: &[&str] = &["cu"];
+
+/// Build C++ and CUDA sources into a single library.
+///
+/// GPU sources (`.cu`) are compiled by the GPU compiler and the
+/// others by the C++ compiler. The objects are archived into one
+/// static library and libtorch is linked once. The `WITH_CUDA` macro
+/// is defined if libtorch supports CUDA. For ROCm builds, GPU sources
+/// are hipified and compiled by [HipExtension], and `WITH_HIP` is
+/// defined as well. Otherwise, GPU sources are skipped and only C++
+/// sources are built.
+///
+/// Each source is compiled into a cached object, see
+/// [compile_incremental()](crate::compile_incremental).
+#[derive(Debug, Clone)]
+pub struct TorchExtension {
+    link_python: bool,
+    force_cuda_link: bool,
+    rpath_policy: RpathPolicy,
+    extra_rpaths: Vec<String>,
+    rpath_targets: Vec<LinkTarget>,
+    compiler_cache: Option<CompilerCache>,
+    compiler_cache_base_dir: Option<PathBuf>,
+    cuda_arch_policy: CudaArchPolicy,
+    ptx_only: bool,
+    compress_fatbin: bool,
+    cxx_flags: Vec<String>,
+    precompiled_header: Option<String>,
+    nvcc_flags: Vec<String>,
+    rdc: bool,
+    compile_commands: bool,
+    compile_commands_dir: Option<PathBuf>,
+    includes: Vec<PathBuf>,
+    link_searches: Vec<PathBuf>,
+    libraries: Vec<String>,
+    headers: Vec<PathBuf>,
+    sources: Vec<PathBuf>,
+}
+
+impl TorchExtension {
+    pub fn new() -> Self {
+        Self {
+            link_python: false,
+            force_cuda_link: false,
+            rpath_policy: RpathPolicy::Absolute,
+            extra_rpaths: vec![],
+            rpath_targets: vec![],
+            compiler_cache: None,
+            compiler_cache_base_dir: None,
+            cuda_arch_policy: CudaArchPolicy::Host,
+            ptx_only: false,
+            compress_fatbin: false,
+            cxx_flags: vec![],
+            precompiled_header: None,
+            nvcc_flags: vec![],
+            rdc: false,
+            compile_commands: false,
+            compile_commands_dir: None,
+            includes: vec![],
+            headers: vec![],
+            sources: vec![],
+            link_searches: vec![],
+            libraries: vec![],
+        }
+    }
+
+    pub fn link_python(&mut self, enabled: bool) -> &mut Self {
+        self.link_python = enabled;
+        self
+    }
+
+    /// Force linking to libtorch CUDA libraries if CUDA is available.
+    /// See [CppExtension::force_cuda_link()].
+    pub fn force_cuda_link(&mut self, enabled: bool) -> &mut Self {
+        self.force_cuda_link = enabled;
+        self
+    }
+
+    /// Set the policy to embed rpath into linked artifacts. It
+    /// defaults to [RpathPolicy::Absolute].
+    pub fn rpath_policy(&mut self, policy: RpathPolicy) -> &mut Self {
+        self.rpath_policy = policy;
+        self
+    }
+
+    /// Add an rpath entry that is embedded verbatim in addition to
+    /// the entries generated by the
+    /// [rpath_policy()](TorchExtension::rpath_policy).
+    pub fn extra_rpath<S>(&mut self, entry: S) -> &mut Self
+    where
+        S: AsRef<str>,
+    {
+        self.extra_rpaths.push(entry.as_ref().to_owned());
+        self
+    }
+
+    pub fn extra_rpaths<S>(&mut self, entries: S) -> &mut Self
+    where
+        S: IntoIterator,
+        S::Item: AsRef<str>,
+    {
+        self.extra_rpaths
+            .extend(entries.into_iter().map(|s| s.as_ref().to_owned()));
+        self
+    }
+
+    /// Add a kind of Cargo targets that rpath applies to. If no
+    /// target is added, rpath applies to all targets.
+    pub fn rpath_target(&mut self, target: LinkTarget) -> &mut Self {
+        self.rpath_targets.push(target);
+        self
+    }
+
+    pub fn rpath_targets<T>(&mut self, targets: T) -> &mut Self
+    where
+        T: IntoIterator<Item = LinkTarget>,
+    {
+        self.rpath_targets.extend(targets);
+        self
+    }
+
+    /// Run compilers through a compiler cache such as ccache or
+    /// sccache.
+    pub fn compiler_cache(&mut self, cache: CompilerCache) -> &mut Self {
+        self.compiler_cache = Some(cache);
+        self
+    }
+
+    /// Rewrite absolute paths under `dir` in compiler arguments
+    /// relative to the working directory, like `CCACHE_BASEDIR`, so
+    /// that cached results are reused across checkouts in different
+    /// locations. It applies only if
+    /// [compiler_cache()](TorchExtension::compiler_cache) is set.
+    pub fn compiler_cache_base_dir<P>(&mut self, dir: P) -> &mut Self
+    where
+        P: AsRef<Path>,
+    {
+        self.compiler_cache_base_dir = Some(dir.as_ref().to_owned());
+        self
+    }
+
+    /// Set the policy to determine CUDA architectures. See
+    /// [CudaExtension::cuda_arch_policy()].
+    pub fn cuda_arch_policy(&mut self, policy: CudaArchPolicy) -> &mut Self {
+        self.cuda_arch_policy = policy;
+        self
+    }
+
+    /// See [CudaExtension::ptx_only()].
+    pub fn ptx_only(&mut self, enabled: bool) -> &mut Self {
+        self.ptx_only = enabled;
+        self
+    }
+
+    /// See [CudaExtension::compress_fatbin()].
+    pub fn compress_fatbin(&mut self, enabled: bool) -> &mut Self {
+        self.compress_fatbin = enabled;
+        self
+    }
+
+    /// Add a flag for the C++ compiler. It is passed to C++ sources
+    /// directly and forwarded to the host compiler for GPU sources.
+    pub fn cxx_flag<S>(&mut self, flag: S) -> &mut Self
+    where
+        S: AsRef<str>,
+    {
+        self.cxx_flags.push(flag.as_ref().to_owned());
+        self
+    }
+
+    pub fn cxx_flags<S>(&mut self, flags: S) -> &mut Self
+    where
+        S: IntoIterator,
+        S::Item: AsRef<str>,
+    {
+        self.cxx_flags
+            .extend(flags.into_iter().map(|s| s.as_ref().to_owned()));
+        self
+    }
+
+    /// Add a flag for nvcc, which only applies to CUDA sources. See
+    /// [CudaExtension::nvcc_flag()].
+    pub fn nvcc_flag<S>(&mut self, flag: S) -> &mut Self
+    where
+        S: AsRef<str>,
+    {
+        self.nvcc_flags.push(flag.as_ref().to_owned());
+        self
+    }
+
+    pub fn nvcc_flags<S>(&mut self, flags: S) -> &mut Self
+    where
+        S: IntoIterator,
+        S::Item: AsRef<str>,
+    {
+        self.nvcc_flags
+            .extend(flags.into_iter().map(|s| s.as_ref().to_owned()));
+        self
+    }
+
+    /// Compile CUDA sources as relocatable device code and link the
+    /// device code into the library. See [CudaExtension::rdc()].
+    pub fn rdc(&mut self, enabled: bool) -> &mut Self {
+        self.rdc = enabled;
+        self
+    }
+
+    /// Precompile a header for C++ sources. See
+    /// [CppExtension::precompiled_header()].
+    pub fn precompiled_header<S>(&mut self, header: S) -> &mut Self
+    where
+        S: AsRef<str>,
+    {
+        self.precompiled_header = Some(header.as_ref().to_owned());
+        self
+    }
+
+    /// Write `compile_commands.json` for C++ and GPU sources. See
+    /// [CppExtension::compile_commands()].
+    pub fn compile_commands(&mut self, enabled: bool) -> &mut Self {
+        self.compile_commands = enabled;
+        self
+    }
+
+    /// Also write `compile_commands.json` to a stable directory. See
+    /// [CppExtension::compile_commands_dir()].
+    pub fn compile_commands_dir<P>(&mut self, dir: P) -> &mut Self
+    where
+        P: AsRef<Path>,
+    {
+        self.compile_commands = true;
+        self.compile_commands_dir = Some(dir.as_ref().to_owned());
+        self
+    }
+
+    pub fn include<P>(&mut self, path: P) -> &mut Self
+    where
+        P: AsRef<Path>,
+    {
+        self.includes.push(path.as_ref().to_owned());
+        self
+    }
+
+    pub fn includes<P>(&mut self, paths: P) -> &mut Self
+    where
+        P: IntoIterator,
+        P::Item: AsRef<Path>,
+    {
+        self.includes
+            .extend(paths.into_iter().map(|p| p.as_ref().to_owned()));
+        self
+    }
+
+    pub fn header<P>(&mut self, path: P) -> &mut Self
+    where
+        P: AsRef<Path>,
+    {
+        self.headers.push(path.as_ref().to_owned());
+        self
+    }
+
+    pub fn headers<P>(&mut self, paths: P) -> &mut Self
+    where
+        P: IntoIterator,
+        P::Item: AsRef<Path>,
+    {
+        self.headers
+            .extend(paths.into_iter().map(|p| p.as_ref().to_owned()));
+        self
+    }
+
+    pub fn source<P>(&mut self, path: P) -> &mut Self
+    where
+        P: AsRef<Path>,
+    {
+        self.sources.push(path.as_ref().to_owned());
+        self
+    }
+
+    pub fn sources<P>(&mut self, paths: P) -> &mut Self
+    where
+        P: IntoIterator,
+        P::Item: AsRef<Path>,
+    {
+        self.sources
+            .extend(paths.into_iter().map(|p| p.as_ref().to_owned()));
+        self
+    }
+
+    pub fn link_search<P>(&mut self, path: P) -> &mut Self
+    where
+        P: AsRef<Path>,
+    {
+        self.link_searches.push(path.as_ref().to_owned());
+        self
+    }
+
+    pub fn link_searches<P>(&mut self, paths: P) -> &mut Self
+    where
+        P: IntoIterator,
+        P::Item: AsRef<Path>,
+    {
+        self.link_searches
+            .extend(paths.into_iter().map(|p| p.as_ref().to_owned()));
+        self
+    }
+
+    pub fn library<P>(&mut self, name: P) -> &mut Self
+    where
+        P: AsRef<str>,
+    {
+        self.libraries.push(name.as_ref().to_owned());
+        self
+    }
+
+    pub fn libraries<P>(&mut self, names: P) -> &mut Self
+    where
+        P: IntoIterator,
+        P::Item: AsRef<str>,
+    {
+        self.libraries
+            .extend(names.into_iter().map(|p| p.as_ref().to_owned()));
+        self
+    }
+
+    /// Compile C++ and GPU source code into the library `name` and
+    /// link it to libtorch.
+    pub fn build(&self, name: &str) -> Result<()> {
+        let objects = self.compile_objects(name, |_| {})?;
+
+        // archive all objects into one library
+        archive_objects(&objects, name)?;
+
+        self.link()?;
+        Ok(())
+    }
+
+    /// Compile C++ and GPU source code into objects. The `configure`
+    /// callback is applied to each [cc::Build] after the common
+    /// options, for example to add extra defines.
+    pub(crate) fn compile_objects<F>(&self, name: &str, configure: F) -> Result<Vec<PathBuf>>
+    where
+        F: Fn(&mut cc::Build),
+    {
+        let libtorch = crate::probe::probe_libtorch()?;
+        let use_gpu = libtorch.is_cuda_api_available();
+        let use_hip = use_gpu && libtorch.api.is_hip();
+        let (gpu_sources, cpp_sources): (Vec<_>, Vec<_>) =
+            self.sources.iter().partition(|path| is_gpu_source(path));
+
+        let out_dir = self.cpp_extension(use_gpu).out_dir()?;
+        let fingerprint = libtorch_fingerprint()?;
+        let launcher = self.launcher();
+        let launcher = launcher.as_ref();
+
+        chain!(&self.sources, &self.headers).for_each(print_cargo_rerun_if_changed);
+
+        // compile C++ sources
+        let mut objects = {
+            let mut build = cc::Build::new();
+            self.cpp_extension(use_gpu)
+                .sources(&cpp_sources)
+                .configure_cc(&mut build)?;
+            define_gpu_macros(&mut build, use_gpu, use_hip);
+            configure(&mut build);
+            self.cxx_flags.iter().for_each(|flag| {
+                build.flag(flag);
+            });
+            if let Some(header) = &self.precompiled_header {
+                configure_pch(&mut build, &out_dir, header, &fingerprint, launcher)?;
+            }
+            let cxx_name = format!("{name}-cxx");
+            self.emit_compile_commands(&build, &out_dir, &cxx_name)?;
+            let objects =
+                compile_incremental_with(&build, &out_dir, &cxx_name, &fingerprint, launcher)?;
+            print_cargo_rerun_if_changed_deps(&build, &out_dir, &cxx_name, &self.includes)?;
+            objects
+        };
+
+        // compile GPU sources
+        if !gpu_sources.is_empty() {
+            if !use_gpu {
+                warn!("CUDA is not supported by libtorch, skip GPU sources {gpu_sources:?}");
+            } else {
+                let mut build = cc::Build::new();
+                if use_hip {
+                    self.hip_extension()
+                        .sources(&gpu_sources)
+                        .configure_cc(&mut build)?;
+                    // hipcc accepts host compiler flags directly
+                    self.cxx_flags.iter().for_each(|flag| {
+                        build.flag(flag);
+                    });
+                    define_gpu_macros(&mut build, use_gpu, use_hip);
+                    configure(&mut build);
+                    let hip_name = format!("{name}-hip");
+                    self.emit_compile_commands(&build, &out_dir, &hip_name)?;
+                    objects.extend(compile_incremental_with(
+                        &build,
+                        &out_dir,
+                        &hip_name,
+                        &fingerprint,
+                        launcher,
+                    )?);
+                    // Include directories are hipified into the output
+                    // directory, so track the originals.
+                    self.includes.iter().for_each(print_cargo_rerun_if_changed);
+                } else {
+                    let mut ext = self.cuda_extension();
+                    ext.sources(&gpu_sources).configure_cc(&mut build)?;
+                    define_gpu_macros(&mut build, use_gpu, use_hip);
+                    configure(&mut build);
+                    let cuda_name = format!("{name}-cuda");
+                    self.emit_compile_commands(&build, &out_dir, &cuda_name)?;
+                    let gpu_objects = compile_incremental_with(
+                        &build,
+                        &out_dir,
+                        &cuda_name,
+                        &fingerprint,
+                        launcher,
+                    )?;
+                    print_cargo_rerun_if_changed_deps(
+                        &build,
+                        &out_dir,
+                        &cuda_name,
+                        &self.includes,
+                    )?;
+
+                    if self.rdc {
+                        objects.push(ext.device_link(&build, &gpu_objects, name)?);
+                    }
+                    objects.extend(gpu_objects);
+                }
+            }
+        }
+
+        Ok(objects)
+    }
+
+    /// Link objects into the shared library `output` with the C++
+    /// compiler. libtorch and the user-specified libraries are linked,
+    /// and `extra_args` are appended to the linker command.
+    pub(crate) fn link_shared<P>(
+        &self,
+        objects: &[P],
+        output: &Path,
+        extra_args: &[String],
+    ) -> Result<()>
+    where
+        P: AsRef<Path>,
+    {
+        cfg_if! {
+            if #[cfg(any(target_os = "linux", target_os = "macos"))] {
+                self.link_shared_unix(objects, output, extra_args)
+            } else {
+                anyhow::bail!("Unsupported OS")
+            }
+        }
+    }
+
+    #[cfg(any(target_os = "linux", target_os = "macos"))]
+    fn link_shared_unix<P>(&self, objects: &[P], output: &Path, extra_args: &[String]) -> Result<()>
+    where
+        P: AsRef<Path>,
+    {
+        let Self {
+            force_cuda_link,
+            rdc,
+            ref rpath_policy,
+            ref extra_rpaths,
+            ref link_searches,
+            ref libraries,
+            ..
+        } = *self;
+
+        let libtorch = crate::probe::probe_libtorch()?;
+        let use_gpu = libtorch.is_cuda_api_available();
+
+        let mut linker = cc::Build::new().cpp(true).try_get_compiler()?.to_command();
+        linker
+            .arg("-shared")
+            .arg("-o")
+            .arg(output)
+            .args(objects.iter().map(|path| path.as_ref()));
+
+        // link libtorch
+        let lib_dirs: Vec<_> =
+            chain!(libtorch.link_paths(use_gpu)?, link_searches.clone()).collect();
+        lib_dirs.iter().for_each(|dir| {
+            linker.arg(format!("-L{}", dir.display()));
+        });
+        super::rpath::rpath_entries_with_extra(rpath_policy, extra_rpaths, &lib_dirs)
+            .iter()
+            .for_each(|entry| {
+                linker.arg(format!("-Wl,-rpath,{entry}"));
+            });
+
+        if force_cuda_link {
+            libtorch.libraries(false, false)?.for_each(|lib| {
+                linker.arg(format!("-l{lib}"));
+            });
+            linker.args(libtorch.cuda_link_args(use_gpu)?);
+        } else {
+            libtorch.libraries(use_gpu, false)?.for_each(|lib| {
+                linker.arg(format!("-l{lib}"));
+            });
+        }
+
+        if rdc && use_gpu && !libtorch.api.is_hip() {
+            linker.arg("-lcudadevrt").arg("-lcudart");
+        }
+
+        // link user-specified libraries
+        libraries.iter().for_each(|lib| {
+            linker.arg(format!("-l{lib}"));
+        });
+
+        linker.args(extra_args);
+
+        let status = linker
+            .status()
+            .with_context(|| format!("unable to run {linker:?}"))?;
+        ensure!(
+            status.success(),
+            "linking {} failed: {linker:?}",
+            output.display()
+        );
+        Ok(())
+    }
+
+    /// Write `compile_commands.json` for the [cc::Build] if enabled.
+    fn emit_compile_commands(&self, build: &cc::Build, out_dir: &Path, name: &str) -> Result<()> {
+        if self.compile_commands {
+            emit_compile_commands(build, out_dir, name, self.compile_commands_dir.as_deref())?;
+        }
+        Ok(())
+    }
+
+    fn launcher(&self) -> Option<Launcher> {
+        Some(Launcher {
+            cache: self.compiler_cache.clone()?,
+            base_dir: self.compiler_cache_base_dir.clone(),
+        })
+    }
+
+    /// Print Cargo instructions to link libtorch. CUDA libraries are
+    /// linked if CUDA is supported by libtorch.
+    pub fn link(&self) -> Result<()> {
+        let libtorch = crate::probe::probe_libtorch()?;
+        let use_gpu = libtorch.is_cuda_api_available();
+        self.cpp_extension(use_gpu).link()?;
+
+        if self.rdc && use_gpu && !libtorch.api.is_hip() {
+            super::cuda::print_cargo_rdc_libraries();
+        }
+        Ok(())
+    }
+
+    /// Configure the [bindgen::Builder] to generate bindings for
+    /// registered headers.
+    #[cfg(feature = "bindgen")]
+    pub fn configure_bindgen(&self, builder: bindgen::Builder) -> Result<bindgen::Builder> {
+        let libtorch = crate::probe::probe_libtorch()?;
+        self.cpp_extension(libtorch.is_cuda_api_available())
+            .configure_bindgen(builder)
+    }
+
+    /// Create a [CppExtension] with common options and without
+    /// sources.
+    fn cpp_extension(&self, use_cuda_api: bool) -> CppExtension {
+        let Self {
+            link_python,
+            force_cuda_link,
+            ref rpath_policy,
+            ref extra_rpaths,
+            ref rpath_targets,
+            ref includes,
+            ref link_searches,
+            ref libraries,
+            ref headers,
+            ..
+        } = *self;
+
+        let mut ext = CppExtension::new();
+        ext.use_cuda_api(use_cuda_api)
+            .link_python(link_python)
+            .force_cuda_link(force_cuda_link)
+            .rpath_policy(rpath_policy.clone())
+            .extra_rpaths(extra_rpaths)
+            .rpath_targets(rpath_targets.iter().copied())
+            .includes(includes)
+            .link_searches(link_searches)
+            .libraries(libraries)
+            .headers(headers);
+        ext
+    }
+
+    /// Create a [CudaExtension] with common options and without
+    /// sources.
+    ///
+    /// The dummy CUDA dependency is compiled by the C++ extension, so
+    /// that `force_cuda_link` is not set here. The compiler cache is
+    /// forwarded for [CudaExtension::device_link()].
+    fn cuda_extension(&self) -> CudaExtension {
+        let Self {
+            link_python,
+            ref compiler_cache,
+            ref compiler_cache_base_dir,
+            ref rpath_policy,
+            ref extra_rpaths,
+            ref rpath_targets,
+            ref cuda_arch_policy,
+            ptx_only,
+            compress_fatbin,
+            ref cxx_flags,
+            ref nvcc_flags,
+            rdc,
+            ref includes,
+            ref link_searches,
+            ref libraries,
+            ref headers,
+            ..
+        } = *self;
+
+        let mut ext = CudaExtension::new();
+        ext.link_python(link_python)
+            .rpath_policy(rpath_policy.clone())
+            .extra_rpaths(extra_rpaths)
+            .rpath_targets(rpath_targets.iter().copied())
+            .cuda_arch_policy(cuda_arch_policy.clone())
+            .ptx_only(ptx_only)
+            .compress_fatbin(compress_fatbin)
+            .host_flags(cxx_flags)
+            .nvcc_flags(nvcc_flags)
+            .rdc(rdc)
+            .includes(includes)
+            .link_searches(link_searches)
+            .libraries(libraries)
+            .headers(headers);
+        if let Some(cache) = compiler_cache {
+            ext.compiler_cache(cache.clone());
+        }
+        if let Some(dir) = compiler_cache_base_dir {
+            ext.compiler_cache_base_dir(dir);
+        }
+        ext
+    }
+
+    /// Create a [HipExtension] with common options and without
+    /// sources.
+    fn hip_extension(&self) -> HipExtension {
+        let Self {
+            link_python,
+            ref compiler_cache,
+            ref compiler_cache_base_dir,
+            ref rpath_policy,
+            ref extra_rpaths,
+            ref rpath_targets,
+            ref includes,
+            ref link_searches,
+            ref libraries,
+            ref headers,
+            ..
+        } = *self;
+
+        let mut ext = HipExtension::new();
+        ext.link_python(link_python)
+            .rpath_policy(rpath_policy.clone())
+            .extra_rpaths(extra_rpaths)
+            .rpath_targets(rpath_targets.iter().copied())
+            .includes(includes)
+            .link_searches(link_searches)
+            .libraries(libraries)
+            .headers(headers);
+        if let Some(cache) = compiler_cache {
+            ext.compiler_cache(cache.clone());
+        }
+        if let Some(dir) = compiler_cache_base_dir {
+            ext.compiler_cache_base_dir(dir);
+        }
+        ext
+    }
+}
+
+impl Default for TorchExtension {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+fn is_gpu_source(path: &Path) -> bool {
+    path.extension()
+        .and_then(|ext| ext.to_str())
+        .map(|ext| GPU_SOURCE_EXTENSIONS.contains(&ext))
+        .unwrap_or(false)
+}
+
+fn define_gpu_macros(build: &mut cc::Build, use_gpu: bool, use_hip: bool) {
+    if use_gpu {
+        build.define("WITH_CUDA", None);
+    }
+    if use_hip {
+        build.define("WITH_HIP", None);
+    }
+}
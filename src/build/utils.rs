@@ -1,4 +1,12 @@
-use std::path::Path;
+use anyhow::Result;
+use std::{
+    fs,
+    path::{Path, PathBuf},
+};
+
+/// The name of the Rust source file generated by
+/// [write_cuda_dependency_rs()].
+pub(crate) const CUDA_DEPENDENCY_RS: &str = "cuda_dependency.rs";
 
 pub fn print_cargo_link_search<P>(path: P)
 where
@@ -6,9 +14,45 @@ where
 {
     let display = path.as_ref().display();
     println!("cargo:rustc-link-search=native={display}");
-    println!("cargo:rustc-link-arg=-Wl,-rpath,{display}");
 }
 
 pub fn print_cargo_link_library(name: &str) {
     println!("cargo:rustc-link-lib={name}",);
 }
+
+pub fn print_cargo_link_arg(arg: &str) {
+    println!("cargo:rustc-link-arg={arg}");
+}
+
+pub fn print_cargo_warning(message: &str) {
+    println!("cargo:warning={message}");
+}
+
+pub fn print_cargo_rerun_if_changed<P>(path: P)
+where
+    P: AsRef<Path>,
+{
+    let display = path.as_ref().display();
+    println!("cargo:rerun-if-changed={display}");
+}
+
+/// Write the Rust source that calls `dummy_cuda_dependency()` to
+/// the output directory and return its path.
+///
+/// The file can be included in the crate with
+/// `include!(concat!(env!("OUT_DIR"), "/cuda_dependency.rs"))`.
+pub(crate) fn write_cuda_dependency_rs(out_dir: &Path) -> Result<PathBuf> {
+    const CODE: &str = r#"extern "C" {
+    fn dummy_cuda_dependency();
+}
+
+/// Reference libtorch CUDA libraries so that they are not dropped by the linker.
+pub fn keep_cuda_dependency() {
+    unsafe { dummy_cuda_dependency() }
+}
+"#;
+
+    let path = out_dir.join(CUDA_DEPENDENCY_RS);
+    fs::write(&path, CODE)?;
+    Ok(path)
+}
@@ -0,0 +1,411 @@
+//! Bundle libtorch and its dependent shared libraries for deployment.
+
+mod elf;
+
+use crate::{probe::probe_python, ProbePython};
+use anyhow::{bail, ensure, Context as _, Result};
+use indexmap::{IndexMap, IndexSet};
+use log::warn;
+use std::{
+    collections::VecDeque,
+    env, fs,
+    os::unix::fs::symlink,
+    path::{Path, PathBuf},
+    process::Command,
+};
+
+/// Library directories searched after the libtorch and Python
+/// library directories.
+const SYSTEM_LIB_DIRS: &[&str] = &[
+    "/lib",
+    "/lib64",
+    "/usr/lib",
+    "/usr/lib64",
+    "/lib/x86_64-linux-gnu",
+    "/usr/lib/x86_64-linux-gnu",
+    "/lib/aarch64-linux-gnu",
+    "/usr/lib/aarch64-linux-gnu",
+];
+
+/// The name prefixes of system libraries that are never bundled.
+///
+/// It includes glibc components, the dynamic loader and the CUDA
+/// driver, which must match the host kernel module.
+const SYSTEM_LIB_PREFIXES: &[&str] = &[
+    "ld-linux",
+    "linux-vdso",
+    "linux-gate",
+    "libc.so",
+    "libm.so",
+    "libdl.so",
+    "librt.so",
+    "libpthread.so",
+    "libutil.so",
+    "libresolv.so",
+    "libnsl.so",
+    "libcrypt.so",
+    "libanl.so",
+    "libmvec.so",
+    "libgcc_s.so",
+    "libcuda.so",
+    "libnvidia-",
+];
+
+/// The name prefixes of C++ runtime libraries, which are bundled
+/// only if [bundle_libstdcxx()](Bundle::bundle_libstdcxx) is
+/// enabled.
+const LIBSTDCXX_PREFIXES: &[&str] = &["libstdc++.so"];
+
+/// Copy shared libraries required by libtorch into `dest_dir`.
+///
+/// This is a shorthand for [Bundle::bundle()] with default options.
+pub fn bundle_libtorch<P>(dest_dir: P) -> Result<Vec<PathBuf>>
+where
+    P: AsRef<Path>,
+{
+    Bundle::new().bundle(dest_dir)
+}
+
+/// Collect libtorch, CUDA, cuDNN, OpenMP and Python shared libraries
+/// into a deployable directory.
+///
+/// The libraries are determined by
+/// [Library::libraries()](crate::Library::libraries) and the
+/// transitive closure of `DT_NEEDED` entries of them and the given
+/// artifacts. System libraries like glibc are skipped.
+#[derive(Debug, Clone)]
+pub struct Bundle {
+    use_cuda_api: Option<bool>,
+    link_python: bool,
+    bundle_libstdcxx: bool,
+    patch_runpath: bool,
+    allow_missing: bool,
+    artifacts: Vec<PathBuf>,
+}
+
+impl Bundle {
+    pub fn new() -> Self {
+        Self {
+            use_cuda_api: None,
+            link_python: false,
+            bundle_libstdcxx: false,
+            patch_runpath: false,
+            allow_missing: false,
+            artifacts: vec![],
+        }
+    }
+
+    /// Set whether to bundle CUDA libraries. By default, CUDA
+    /// libraries are bundled if CUDA is supported by libtorch.
+    pub fn use_cuda_api(&mut self, enabled: bool) -> &mut Self {
+        self.use_cuda_api = Some(enabled);
+        self
+    }
+
+    pub fn link_python(&mut self, enabled: bool) -> &mut Self {
+        self.link_python = enabled;
+        self
+    }
+
+    /// Bundle `libstdc++`, which is treated as a system library by
+    /// default.
+    pub fn bundle_libstdcxx(&mut self, enabled: bool) -> &mut Self {
+        self.bundle_libstdcxx = enabled;
+        self
+    }
+
+    /// Set RUNPATH of bundled libraries to `$ORIGIN` using `patchelf`.
+    pub fn patch_runpath(&mut self, enabled: bool) -> &mut Self {
+        self.patch_runpath = enabled;
+        self
+    }
+
+    /// Skip libraries that cannot be found with a warning. By default,
+    /// [bundle()](Bundle::bundle) fails and reports the missing
+    /// libraries, since the bundle would be incomplete.
+    pub fn allow_missing(&mut self, enabled: bool) -> &mut Self {
+        self.allow_missing = enabled;
+        self
+    }
+
+    /// Add an artifact, such as the final executable, whose
+    /// dependencies are bundled as well. The artifact itself is not
+    /// copied.
+    pub fn artifact<P>(&mut self, path: P) -> &mut Self
+    where
+        P: AsRef<Path>,
+    {
+        self.artifacts.push(path.as_ref().to_owned());
+        self
+    }
+
+    pub fn artifacts<P>(&mut self, paths: P) -> &mut Self
+    where
+        P: IntoIterator,
+        P::Item: AsRef<Path>,
+    {
+        self.artifacts
+            .extend(paths.into_iter().map(|p| p.as_ref().to_owned()));
+        self
+    }
+
+    /// Copy the required shared libraries into `dest_dir` and return
+    /// the paths of copied files.
+    ///
+    /// The soname symlinks of each library are recreated in
+    /// `dest_dir`.
+    pub fn bundle<P>(&self, dest_dir: P) -> Result<Vec<PathBuf>>
+    where
+        P: AsRef<Path>,
+    {
+        let dest_dir = dest_dir.as_ref();
+        let libraries = self.resolve_libraries()?;
+
+        fs::create_dir_all(dest_dir)
+            .with_context(|| format!("unable to create directory {}", dest_dir.display()))?;
+
+        let mut copied = vec![];
+        for (real_path, link_paths) in &libraries {
+            let file = copy_with_symlinks(real_path, link_paths, dest_dir)?;
+
+            if self.patch_runpath {
+                patch_runpath(&file)?;
+            }
+
+            copied.push(file);
+        }
+
+        Ok(copied)
+    }
+
+    /// Find the shared libraries to be bundled. It returns a map from
+    /// the canonical path of each library to the paths referring to
+    /// it, which are usually symlinks.
+    fn resolve_libraries(&self) -> Result<IndexMap<PathBuf, Vec<PathBuf>>> {
+        let Self {
+            use_cuda_api,
+            link_python,
+            ref artifacts,
+            ..
+        } = *self;
+
+        let libtorch = crate::probe::probe_libtorch()?;
+        let mut search_dirs: Vec<PathBuf> = libtorch.link_paths(use_cuda_api)?.collect();
+        let mut link_names: Vec<String> = libtorch
+            .libraries(use_cuda_api, link_python)?
+            .map(|name| name.to_string())
+            .collect();
+
+        if link_python {
+            let ProbePython {
+                link_searches,
+                libraries,
+                ..
+            } = probe_python()?;
+            search_dirs.extend(link_searches);
+            link_names.extend(libraries);
+        }
+
+        if let Some(paths) = env::var_os("LD_LIBRARY_PATH") {
+            search_dirs.extend(env::split_paths(&paths));
+        }
+        search_dirs.extend(SYSTEM_LIB_DIRS.iter().map(PathBuf::from));
+
+        let mut resolved: IndexMap<PathBuf, Vec<PathBuf>> = IndexMap::new();
+        let mut queue = VecDeque::new();
+        let mut missing = vec![];
+
+        // The libraries passed to the linker are the roots.
+        for name in link_names {
+            let file_name = format!("lib{name}.so");
+            if self.is_excluded(&file_name) {
+                continue;
+            }
+
+            match find_library(&file_name, &[], &search_dirs) {
+                Some(path) => queue.push_back(path),
+                None => missing.push(file_name),
+            }
+        }
+
+        // Artifacts are not bundled, but their dependencies are.
+        for artifact in artifacts {
+            ensure!(
+                elf::is_elf(artifact),
+                "{} is not an ELF file",
+                artifact.display()
+            );
+            let info = elf::read_dynamic_info(artifact)?;
+            self.enqueue_needed(artifact, &info, &search_dirs, &mut queue, &mut missing);
+        }
+
+        // Walk through the DT_NEEDED closure.
+        while let Some(path) = queue.pop_front() {
+            let real_path = fs::canonicalize(&path)
+                .with_context(|| format!("unable to resolve {}", path.display()))?;
+
+            if let Some(link_paths) = resolved.get_mut(&real_path) {
+                if !link_paths.contains(&path) {
+                    link_paths.push(path);
+                }
+                continue;
+            }
+
+            let info = elf::read_dynamic_info(&real_path)?;
+            self.enqueue_needed(&path, &info, &search_dirs, &mut queue, &mut missing);
+
+            let mut link_paths = vec![path];
+            if let Some(soname) = &info.soname {
+                let soname_path = real_path.with_file_name(soname);
+                if soname_path.exists() && !link_paths.contains(&soname_path) {
+                    link_paths.push(soname_path);
+                }
+            }
+            resolved.insert(real_path, link_paths);
+        }
+
+        if !missing.is_empty() {
+            ensure!(
+                self.allow_missing,
+                "unable to find libraries: {}",
+                missing.join(", ")
+            );
+            missing.iter().for_each(|name| {
+                warn!("unable to find library {name}, skipped");
+            });
+        }
+
+        Ok(resolved)
+    }
+
+    fn enqueue_needed(
+        &self,
+        parent: &Path,
+        info: &elf::DynamicInfo,
+        search_dirs: &[PathBuf],
+        queue: &mut VecDeque<PathBuf>,
+        missing: &mut Vec<String>,
+    ) {
+        // RPATH is ignored by the loader if RUNPATH is present.
+        let origin = parent.parent().unwrap_or_else(|| Path::new("."));
+        let entries = if info.runpath.is_empty() {
+            &info.rpath
+        } else {
+            &info.runpath
+        };
+        let rpath_dirs: Vec<PathBuf> = entries
+            .iter()
+            .map(|entry| {
+                let entry = entry
+                    .replace("${ORIGIN}", "$ORIGIN")
+                    .replace("$ORIGIN", &origin.display().to_string());
+                PathBuf::from(entry)
+            })
+            .collect();
+
+        for name in &info.needed {
+            if self.is_excluded(name) {
+                continue;
+            }
+
+            match find_library(name, &rpath_dirs, search_dirs) {
+                Some(path) => queue.push_back(path),
+                None => missing.push(format!("{name} (required by {})", parent.display())),
+            }
+        }
+    }
+
+    fn is_excluded(&self, name: &str) -> bool {
+        let is_system = SYSTEM_LIB_PREFIXES
+            .iter()
+            .any(|prefix| name.starts_with(prefix));
+        let is_libstdcxx = LIBSTDCXX_PREFIXES
+            .iter()
+            .any(|prefix| name.starts_with(prefix));
+        is_system || (is_libstdcxx && !self.bundle_libstdcxx)
+    }
+}
+
+impl Default for Bundle {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+fn find_library(name: &str, rpath_dirs: &[PathBuf], search_dirs: &[PathBuf]) -> Option<PathBuf> {
+    rpath_dirs
+        .iter()
+        .chain(search_dirs)
+        .map(|dir| dir.join(name))
+        .find(|path| path.exists())
+}
+
+/// Copy the library file into `dest_dir` and recreate the symlinks
+/// along the paths referring to it. It returns the path of the
+/// copied file.
+fn copy_with_symlinks(
+    real_path: &Path,
+    link_paths: &[PathBuf],
+    dest_dir: &Path,
+) -> Result<PathBuf> {
+    let Some(real_name) = real_path.file_name() else {
+        bail!("invalid library path {}", real_path.display());
+    };
+    let dest_file = dest_dir.join(real_name);
+
+    if dest_file.exists() {
+        fs::remove_file(&dest_file)?;
+    }
+    fs::copy(real_path, &dest_file).with_context(|| {
+        format!(
+            "unable to copy {} to {}",
+            real_path.display(),
+            dest_file.display()
+        )
+    })?;
+
+    // Collect file names along each symlink chain
+    let mut link_names = IndexSet::new();
+    for path in link_paths {
+        let mut current = path.to_owned();
+        link_names.insert(current.file_name().unwrap().to_owned());
+
+        while fs::symlink_metadata(&current)?.file_type().is_symlink() {
+            let target = fs::read_link(&current)?;
+            current = match current.parent() {
+                Some(dir) => dir.join(target),
+                None => target,
+            };
+            link_names.insert(current.file_name().unwrap().to_owned());
+        }
+    }
+
+    for link_name in link_names {
+        if link_name == real_name {
+            continue;
+        }
+
+        let link = dest_dir.join(&link_name);
+        if fs::symlink_metadata(&link).is_ok() {
+            fs::remove_file(&link)?;
+        }
+        symlink(real_name, &link)?;
+    }
+
+    Ok(dest_file)
+}
+
+fn patch_runpath(file: &Path) -> Result<()> {
+    let status = Command::new("patchelf")
+        .arg("--set-rpath")
+        .arg("$ORIGIN")
+        .arg(file)
+        .status()
+        .with_context(|| "unable to run `patchelf`")?;
+    ensure!(
+        status.success(),
+        "`patchelf` failed to patch RUNPATH of {}",
+        file.display()
+    );
+    Ok(())
+}
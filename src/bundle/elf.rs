@@ -0,0 +1,244 @@
+//! Minimal reader for the dynamic section of ELF files.
+
+use anyhow::{anyhow, bail, ensure, Context as _, Result};
+use std::{
+    fs,
+    io::{prelude::*, SeekFrom},
+    path::Path,
+};
+
+const PT_LOAD: u32 = 1;
+const PT_DYNAMIC: u32 = 2;
+
+const DT_NULL: u64 = 0;
+const DT_NEEDED: u64 = 1;
+const DT_STRTAB: u64 = 5;
+const DT_STRSZ: u64 = 10;
+const DT_SONAME: u64 = 14;
+const DT_RPATH: u64 = 15;
+const DT_RUNPATH: u64 = 29;
+
+/// The dynamic linking information of an ELF file.
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub(crate) struct DynamicInfo {
+    pub soname: Option<String>,
+    pub needed: Vec<String>,
+    pub rpath: Vec<String>,
+    pub runpath: Vec<String>,
+}
+
+struct Reader {
+    file: fs::File,
+    is_64: bool,
+    is_le: bool,
+}
+
+impl Reader {
+    fn read_bytes(&mut self, offset: u64, len: usize) -> Result<Vec<u8>> {
+        let mut buf = vec![0; len];
+        self.file.seek(SeekFrom::Start(offset))?;
+        self.file.read_exact(&mut buf)?;
+        Ok(buf)
+    }
+
+    fn u16(&self, bytes: &[u8]) -> u16 {
+        let bytes = bytes[..2].try_into().unwrap();
+        if self.is_le {
+            u16::from_le_bytes(bytes)
+        } else {
+            u16::from_be_bytes(bytes)
+        }
+    }
+
+    fn u32(&self, bytes: &[u8]) -> u32 {
+        let bytes = bytes[..4].try_into().unwrap();
+        if self.is_le {
+            u32::from_le_bytes(bytes)
+        } else {
+            u32::from_be_bytes(bytes)
+        }
+    }
+
+    fn u64(&self, bytes: &[u8]) -> u64 {
+        let bytes = bytes[..8].try_into().unwrap();
+        if self.is_le {
+            u64::from_le_bytes(bytes)
+        } else {
+            u64::from_be_bytes(bytes)
+        }
+    }
+
+    /// Read a word, which is 8 bytes on ELF64 and 4 bytes on ELF32.
+    fn word(&self, bytes: &[u8]) -> u64 {
+        if self.is_64 {
+            self.u64(bytes)
+        } else {
+            self.u32(bytes) as u64
+        }
+    }
+}
+
+struct Segment {
+    kind: u32,
+    offset: u64,
+    vaddr: u64,
+    filesz: u64,
+}
+
+/// Check if the file starts with the ELF magic number.
+pub(crate) fn is_elf(path: &Path) -> bool {
+    let mut magic = [0u8; 4];
+    fs::File::open(path)
+        .and_then(|mut file| file.read_exact(&mut magic))
+        .is_ok()
+        && magic == *b"\x7fELF"
+}
+
+/// Read the dynamic section of an ELF file.
+///
+/// It only reads the headers and the dynamic section, so that large
+/// libraries are not loaded into memory.
+pub(crate) fn read_dynamic_info(path: &Path) -> Result<DynamicInfo> {
+    read_dynamic_info_private(path)
+        .with_context(|| format!("unable to read ELF file {}", path.display()))
+}
+
+fn read_dynamic_info_private(path: &Path) -> Result<DynamicInfo> {
+    let file = fs::File::open(path)?;
+    let mut reader = Reader {
+        file,
+        is_64: true,
+        is_le: true,
+    };
+
+    let ident = reader.read_bytes(0, 16)?;
+    ensure!(&ident[0..4] == b"\x7fELF", "not an ELF file");
+    reader.is_64 = match ident[4] {
+        1 => false,
+        2 => true,
+        class => bail!("unknown ELF class {class}"),
+    };
+    reader.is_le = match ident[5] {
+        1 => true,
+        2 => false,
+        data => bail!("unknown ELF data encoding {data}"),
+    };
+
+    // Locate program headers
+    let (phoff, phentsize, phnum) = if reader.is_64 {
+        let header = reader.read_bytes(0, 64)?;
+        (
+            reader.u64(&header[32..]),
+            reader.u16(&header[54..]),
+            reader.u16(&header[56..]),
+        )
+    } else {
+        let header = reader.read_bytes(0, 52)?;
+        (
+            reader.u32(&header[28..]) as u64,
+            reader.u16(&header[42..]),
+            reader.u16(&header[44..]),
+        )
+    };
+
+    let segments: Vec<Segment> = (0..phnum as u64)
+        .map(|index| -> Result<_> {
+            let offset = phoff + index * phentsize as u64;
+            let header = reader.read_bytes(offset, phentsize as usize)?;
+            let kind = reader.u32(&header[0..]);
+
+            let segment = if reader.is_64 {
+                Segment {
+                    kind,
+                    offset: reader.u64(&header[8..]),
+                    vaddr: reader.u64(&header[16..]),
+                    filesz: reader.u64(&header[32..]),
+                }
+            } else {
+                Segment {
+                    kind,
+                    offset: reader.u32(&header[4..]) as u64,
+                    vaddr: reader.u32(&header[8..]) as u64,
+                    filesz: reader.u32(&header[16..]) as u64,
+                }
+            };
+            Ok(segment)
+        })
+        .collect::<Result<_>>()?;
+
+    // Statically linked files have no dynamic section.
+    let Some(dynamic) = segments.iter().find(|seg| seg.kind == PT_DYNAMIC) else {
+        return Ok(DynamicInfo::default());
+    };
+
+    // Read dynamic entries
+    let entry_size = if reader.is_64 { 16 } else { 8 };
+    let word_size = entry_size / 2;
+    let bytes = reader.read_bytes(dynamic.offset, dynamic.filesz as usize)?;
+    let entries: Vec<(u64, u64)> = bytes
+        .chunks_exact(entry_size)
+        .map(|entry| (reader.word(entry), reader.word(&entry[word_size..])))
+        .take_while(|&(tag, _)| tag != DT_NULL)
+        .collect();
+
+    let find_value = |tag: u64| {
+        entries
+            .iter()
+            .find(|&&(entry_tag, _)| entry_tag == tag)
+            .map(|&(_, value)| value)
+    };
+    let strtab_addr = find_value(DT_STRTAB).ok_or_else(|| anyhow!("DT_STRTAB is missing"))?;
+    let strtab_size = find_value(DT_STRSZ).ok_or_else(|| anyhow!("DT_STRSZ is missing"))?;
+
+    // Translate the virtual address of string table to file offset
+    let strtab_offset = segments
+        .iter()
+        .filter(|seg| seg.kind == PT_LOAD)
+        .find(|seg| seg.vaddr <= strtab_addr && strtab_addr < seg.vaddr + seg.filesz)
+        .map(|seg| strtab_addr - seg.vaddr + seg.offset)
+        .ok_or_else(|| anyhow!("DT_STRTAB is not in any loadable segment"))?;
+    let strtab = reader.read_bytes(strtab_offset, strtab_size as usize)?;
+
+    let get_str = |offset: u64| -> Result<String> {
+        let bytes = strtab
+            .get(offset as usize..)
+            .ok_or_else(|| anyhow!("string offset {offset} is out of bounds"))?;
+        let len = bytes.iter().position(|&b| b == 0).unwrap_or(bytes.len());
+        Ok(String::from_utf8_lossy(&bytes[..len]).into_owned())
+    };
+    let split_paths = |text: String| -> Vec<String> {
+        text.split(':')
+            .filter(|path| !path.is_empty())
+            .map(|path| path.to_string())
+            .collect()
+    };
+
+    let mut info = DynamicInfo::default();
+
+    for &(tag, value) in &entries {
+        match tag {
+            DT_NEEDED => info.needed.push(get_str(value)?),
+            DT_SONAME => info.soname = Some(get_str(value)?),
+            DT_RPATH => info.rpath.extend(split_paths(get_str(value)?)),
+            DT_RUNPATH => info.runpath.extend(split_paths(get_str(value)?)),
+            _ => {}
+        }
+    }
+
+    Ok(info)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{is_elf, read_dynamic_info};
+    use std::env;
+
+    #[test]
+    fn read_current_exe() {
+        let exe = env::current_exe().unwrap();
+        assert!(is_elf(&exe));
+
+        let info = read_dynamic_info(&exe).unwrap();
+        assert!(info.needed.iter().any(|name| name.starts_with("libc.so")));
+    }
+}
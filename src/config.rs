@@ -1,23 +1,173 @@
-use anyhow::{anyhow, Error, Result};
+use anyhow::{anyhow, bail, Error, Result};
 use once_cell::sync::Lazy;
-use serde::{de::Error as _, Deserialize, Deserializer};
-use std::{
-    collections::{HashMap, HashSet},
-    str::FromStr,
-};
+use serde::{de::Error as _, Deserialize, Deserializer, Serialize, Serializer};
+use std::{collections::HashMap, fmt, str::FromStr};
 
 #[derive(Debug, Clone, Deserialize)]
+#[non_exhaustive]
 pub struct Config {
     pub torch_version: String,
-    pub torch_cuda_arch_list: HashSet<CudaArch>,
-    pub cuda_arch_aliases: HashMap<String, Vec<CudaArch>>,
+}
+
+/// The database of CUDA architectures, keyed by CUDA toolkit and
+/// libtorch versions.
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct CudaArchDb {
+    /// Named architecture families, such as `Ampere`.
+    #[serde(default)]
+    pub families: HashMap<String, Vec<CudaArch>>,
+    /// Architectures supported by CUDA toolkits, sorted by version.
+    #[serde(default)]
+    pub toolkits: Vec<ToolkitArches>,
+    /// The default architectures of libtorch releases, sorted by
+    /// version.
+    #[serde(default)]
+    pub libtorch: Vec<LibtorchArches>,
+}
+
+/// Architectures supported by a CUDA toolkit.
+#[derive(Debug, Clone, Deserialize)]
+pub struct ToolkitArches {
+    pub cuda: ReleaseVersion,
+    pub arches: Vec<CudaArch>,
+}
+
+/// The default architectures of a libtorch release.
+#[derive(Debug, Clone, Deserialize)]
+pub struct LibtorchArches {
+    pub version: ReleaseVersion,
+    pub cuda_arch_list: Vec<CudaArch>,
+}
+
+impl CudaArchDb {
+    /// Merge entries from `other` into this database. The entries in
+    /// `other` take precedence.
+    pub fn merge(&mut self, other: CudaArchDb) {
+        let CudaArchDb {
+            families,
+            toolkits,
+            libtorch,
+        } = other;
+
+        self.families.extend(families);
+
+        for entry in toolkits {
+            self.toolkits.retain(|orig| orig.cuda != entry.cuda);
+            self.toolkits.push(entry);
+        }
+        self.toolkits.sort_by_key(|entry| entry.cuda);
+
+        for entry in libtorch {
+            self.libtorch.retain(|orig| orig.version != entry.version);
+            self.libtorch.push(entry);
+        }
+        self.libtorch.sort_by_key(|entry| entry.version);
+    }
+
+    /// Get the architectures supported by the CUDA toolkit of the
+    /// given version.
+    pub fn toolkit_arches(&self, cuda: ReleaseVersion) -> Option<&[CudaArch]> {
+        let entry = self
+            .toolkits
+            .iter()
+            .rev()
+            .find(|entry| entry.cuda <= cuda)?;
+        Some(&entry.arches)
+    }
+
+    /// Get the default architectures of the libtorch release of the
+    /// given version. The oldest entry is used if the version is
+    /// older than all entries.
+    pub fn libtorch_arches(&self, version: ReleaseVersion) -> Option<&[CudaArch]> {
+        let entry = self
+            .libtorch
+            .iter()
+            .rev()
+            .find(|entry| entry.version <= version)
+            .or_else(|| self.libtorch.first())?;
+        Some(&entry.cuda_arch_list)
+    }
+
+    /// Derive the default `TORCH_CUDA_ARCH_LIST` for the libtorch and
+    /// CUDA toolkit versions, in sorted order.
+    ///
+    /// Architectures not supported by the toolkit are dropped. If
+    /// none is left, all architectures of the toolkit are used.
+    pub fn default_cuda_arch_list(
+        &self,
+        torch: ReleaseVersion,
+        cuda: Option<ReleaseVersion>,
+    ) -> Vec<CudaArch> {
+        let libtorch_arches = self.libtorch_arches(torch).unwrap_or(&[]);
+        let toolkit_arches = cuda.and_then(|cuda| self.toolkit_arches(cuda));
+
+        let mut arches: Vec<CudaArch> = match toolkit_arches {
+            Some(supported) => {
+                let arches: Vec<_> = libtorch_arches
+                    .iter()
+                    .filter(|arch| supported.iter().any(|other| other.same_target(arch)))
+                    .cloned()
+                    .collect();
+
+                if arches.is_empty() {
+                    supported.to_vec()
+                } else {
+                    arches
+                }
+            }
+            None => libtorch_arches.to_vec(),
+        };
+        arches.sort();
+        arches.dedup();
+        arches
+    }
+}
+
+/// A release version `X.Y`. The patch number is ignored in parsing.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct ReleaseVersion {
+    pub major: u32,
+    pub minor: u32,
+}
+
+impl FromStr for ReleaseVersion {
+    type Err = Error;
+
+    fn from_str(text: &str) -> Result<Self, Self::Err> {
+        static REGEX_VERSION: Lazy<regex::Regex> =
+            Lazy::new(|| regex::Regex::new(r"^(\d+)\.(\d+)(\.\d+)?$").unwrap());
+
+        let cap = REGEX_VERSION
+            .captures(text)
+            .ok_or_else(|| anyhow!(r#"invalid version "{}""#, text))?;
+
+        Ok(Self {
+            major: cap[1].parse()?,
+            minor: cap[2].parse()?,
+        })
+    }
+}
+
+impl<'de> Deserialize<'de> for ReleaseVersion {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        let text = String::deserialize(deserializer)?;
+        text.parse()
+            .map_err(|err| D::Error::custom(format!("'{text}' is not a valid version: {err}")))
+    }
 }
 
 /// The CUDA architecture version.
 #[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+#[non_exhaustive]
 pub struct CudaArch {
     pub major: u32,
     pub minor: u32,
+    /// Whether to enable architecture-specific features, which is
+    /// written as `X.Ya` or `sm_XYa`.
+    pub arch_specific: bool,
     pub with_ptx: bool,
 }
 
@@ -35,33 +185,108 @@ impl<'de> Deserialize<'de> for CudaArch {
 }
 
 impl CudaArch {
+    /// Check if both refer to the same compilation target regardless
+    /// of PTX.
+    pub fn same_target(&self, other: &CudaArch) -> bool {
+        (self.major, self.minor, self.arch_specific)
+            == (other.major, other.minor, other.arch_specific)
+    }
+
+    /// The architecture number in nvcc spelling, for example `86` or
+    /// `90a`.
+    pub fn number(&self) -> String {
+        let suffix = if self.arch_specific { "a" } else { "" };
+        format!("{}{}{}", self.major, self.minor, suffix)
+    }
+
     /// Generate the nvcc flag for this architecture.
     ///
     /// It generates the flag if version is `X.Y`.
-    /// - If `with_ptx=true`, `-gencode=arch=sm_X,code=sm_Y`
-    /// - If `with_ptx=true`, `-gencode=arch=compute_X,code=sm_Y`
+    /// - If `with_ptx=false`, `-gencode=arch=compute_XY,code=sm_XY`
+    /// - If `with_ptx=true`, `-gencode=arch=compute_XY,code=[sm_XY,compute_XY]`
     pub fn nvcc_flag(&self) -> String {
+        let number = self.number();
+
+        if self.with_ptx {
+            format!("-gencode=arch=compute_{number},code=[sm_{number},compute_{number}]")
+        } else {
+            format!("-gencode=arch=compute_{number},code=sm_{number}")
+        }
+    }
+
+    /// Generate the nvcc flag that embeds only PTX for this
+    /// architecture, `-gencode=arch=compute_XY,code=compute_XY`.
+    ///
+    /// The PTX is compiled by the driver at load time, so that the
+    /// binary runs on this and newer architectures.
+    pub fn nvcc_ptx_flag(&self) -> String {
+        let number = self.number();
+        format!("-gencode=arch=compute_{number},code=compute_{number}")
+    }
+}
+
+impl fmt::Display for CudaArch {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         let Self {
             major,
             minor,
+            arch_specific,
             with_ptx,
         } = *self;
-        let number = format!("{}{}", major, minor);
-        let code_kind = if with_ptx { "compute" } else { "sm" };
+        write!(f, "{major}.{minor}")?;
+        if arch_specific {
+            write!(f, "a")?;
+        }
+        if with_ptx {
+            write!(f, "+PTX")?;
+        }
+        Ok(())
+    }
+}
 
-        format!(
-            "-gencode=arch=compute_{},code={}_{}",
-            number, code_kind, number
-        )
+impl Serialize for CudaArch {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        serializer.collect_str(self)
     }
 }
 
 impl FromStr for CudaArch {
     type Err = Error;
 
+    /// Parse the version `X.Y`, `X.Ya` or `X.Y+PTX`, or the nvcc
+    /// spelling `sm_XY` or `sm_XYa`.
+    ///
+    /// The nvcc spelling `compute_XY` is rejected. It means PTX only
+    /// to nvcc, which is not expressible here, while `X.Y+PTX` embeds
+    /// both SASS and PTX.
     fn from_str(text: &str) -> Result<Self, Self::Err> {
         static REGEX_CUDA_ARCH: Lazy<regex::Regex> =
-            Lazy::new(|| regex::Regex::new(r"^(\d+)\.(\d+)(\+PTX)?$").unwrap());
+            Lazy::new(|| regex::Regex::new(r"^(\d+)\.(\d+)(a)?(\+PTX)?$").unwrap());
+        static REGEX_NVCC_ARCH: Lazy<regex::Regex> =
+            Lazy::new(|| regex::Regex::new(r"^(sm|compute)_(\d+)(\d)(a)?$").unwrap());
+
+        if let Some(cap) = REGEX_NVCC_ARCH.captures(text) {
+            let major = cap[2].parse()?;
+            let minor = cap[3].parse()?;
+            let arch_specific = cap.get(4).is_some();
+            let suffix = if arch_specific { "a" } else { "" };
+
+            if &cap[1] == "compute" {
+                bail!(
+                    r#"PTX-only CUDA arch "{text}" is not supported, use "{major}.{minor}{suffix}+PTX" to embed SASS and PTX"#
+                );
+            }
+
+            return Ok(Self {
+                major,
+                minor,
+                arch_specific,
+                with_ptx: false,
+            });
+        }
 
         let cap = REGEX_CUDA_ARCH
             .captures(text)
@@ -69,12 +294,58 @@ impl FromStr for CudaArch {
 
         let major = cap.get(1).unwrap().as_str().parse().unwrap();
         let minor = cap.get(2).unwrap().as_str().parse().unwrap();
-        let with_ptx = cap.get(3).is_some();
+        let arch_specific = cap.get(3).is_some();
+        let with_ptx = cap.get(4).is_some();
 
         Ok(Self {
             major,
             minor,
+            arch_specific,
             with_ptx,
         })
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::CudaArch;
+
+    #[test]
+    fn cuda_arch_round_trip() {
+        for text in ["8.6", "8.6+PTX", "9.0a", "9.0a+PTX", "12.0"] {
+            let arch: CudaArch = text.parse().unwrap();
+            assert_eq!(arch.to_string(), text);
+
+            let value = toml::Value::try_from(&arch).unwrap();
+            let arch2: CudaArch = value.try_into().unwrap();
+            assert_eq!(arch, arch2);
+        }
+    }
+
+    #[test]
+    fn cuda_arch_nvcc_spelling() {
+        let arch: CudaArch = "sm_86".parse().unwrap();
+        assert_eq!(arch.to_string(), "8.6");
+        let arch: CudaArch = "sm_90a".parse().unwrap();
+        assert_eq!(arch.to_string(), "9.0a");
+
+        let err = "compute_90".parse::<CudaArch>().unwrap_err();
+        assert!(err.to_string().contains("9.0+PTX"));
+    }
+
+    #[test]
+    fn cuda_arch_nvcc_flag() {
+        let arch: CudaArch = "8.6".parse().unwrap();
+        assert_eq!(arch.nvcc_flag(), "-gencode=arch=compute_86,code=sm_86");
+
+        let arch: CudaArch = "9.0a+PTX".parse().unwrap();
+        assert_eq!(
+            arch.nvcc_flag(),
+            "-gencode=arch=compute_90a,code=[sm_90a,compute_90a]"
+        );
+        assert_eq!(
+            arch.nvcc_ptx_flag(),
+            "-gencode=arch=compute_90a,code=compute_90a"
+        );
+    }
+}
@@ -1,43 +1,108 @@
 //! CUDA related types and functions.
 
-use crate::{config::CudaArch, env::TORCH_CUDA_ARCH_LIST};
-use anyhow::Result;
+use crate::{
+    build::utils::print_cargo_warning,
+    config::CudaArch,
+    config::ReleaseVersion,
+    env::{CUDA_ARCH_DB, CUDA_HOME, TORCH_CUDA_ARCH_LIST},
+    library::Library,
+};
+use anyhow::{anyhow, ensure, Result};
 use indexmap::IndexSet;
-use itertools::Itertools as _;
+use log::warn;
 use once_cell::sync::{Lazy, OnceCell};
-use std::cmp;
+use regex::Regex;
+use std::{
+    cmp, fmt,
+    path::{Path, PathBuf},
+    process::Command,
+    str,
+};
+
+/// The policy to determine CUDA architectures to compile for.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Default)]
+pub enum CudaArchPolicy {
+    /// Detect GPUs on the host system. If no GPU is detected, it
+    /// falls back to [CudaArchPolicy::TorchCudaArchList].
+    #[default]
+    Host,
+    /// Use the given architectures.
+    Explicit(Vec<CudaArch>),
+    /// Use the architectures given by `TORCH_CUDA_ARCH_LIST`
+    /// environment variable, or the default supported architectures
+    /// if it is not set. The newest architecture embeds PTX.
+    TorchCudaArchList,
+    /// Use the architectures that libtorch CUDA libraries are
+    /// compiled for. It requires `cuobjdump` in the CUDA installation.
+    Libtorch,
+}
+
+impl CudaArchPolicy {
+    /// Resolve the list of architectures sorted by version.
+    pub fn resolve(&self) -> Result<Vec<CudaArch>> {
+        let arches = match self {
+            Self::Host => cuda_arches()?.to_vec(),
+            Self::Explicit(arches) => {
+                ensure!(!arches.is_empty(), "no CUDA architecture is specified");
+                let arches: IndexSet<_> = arches.iter().cloned().collect();
+                let mut arches: Vec<_> = arches.into_iter().collect();
+                arches.sort();
+                arches
+            }
+            Self::TorchCudaArchList => torch_cuda_arches().to_vec(),
+            Self::Libtorch => {
+                // cuobjdump is slow on large libraries, so the result
+                // is cached for the probed libtorch.
+                static ARCHES: OnceCell<Vec<CudaArch>> = OnceCell::new();
+                ARCHES
+                    .get_or_try_init(|| libtorch_cuda_arches(crate::probe::probe_libtorch()?))?
+                    .clone()
+            }
+        };
+        Ok(arches)
+    }
+}
 
 /// Generate compatible architecture for the host system.
+///
+/// If no GPU is detected or the CUDA driver is not available, the
+/// architectures given by `TORCH_CUDA_ARCH_LIST` are returned, so
+/// that GPU-less machines get a deterministic list.
 pub fn cuda_arches() -> Result<&'static [CudaArch]> {
-    static MAX_CUDA_ARCH: Lazy<(u32, u32)> = Lazy::new(|| {
-        let max = TORCH_CUDA_ARCH_LIST.iter().max().unwrap();
-        (max.major, max.minor)
+    static MAX_CUDA_ARCH: Lazy<Option<(u32, u32)>> = Lazy::new(|| {
+        let max = TORCH_CUDA_ARCH_LIST.iter().max()?;
+        Some((max.major, max.minor))
     });
 
     static ARCHES: OnceCell<Vec<CudaArch>> = OnceCell::new();
 
     let arches = ARCHES.get_or_try_init(|| -> Result<_> {
-        use rustacuda::{
-            device::{Device, DeviceAttribute::*},
-            CudaFlags,
+        let device_arches = match host_device_arches() {
+            Ok(arches) => arches,
+            Err(err) => {
+                warn!("unable to detect CUDA devices: {err}");
+                vec![]
+            }
         };
 
-        rustacuda::init(CudaFlags::empty())?;
+        if device_arches.is_empty() {
+            warn!("no CUDA device is detected, use architectures in TORCH_CUDA_ARCH_LIST");
+            return Ok(torch_cuda_arches().to_vec());
+        }
 
-        let host_arches: IndexSet<_> = Device::devices()?
-            .map(|device| -> Result<_> {
-                let device = device?;
-                let major = device.get_attribute(ComputeCapabilityMajor)? as u32;
-                let minor = device.get_attribute(ComputeCapabilityMinor)? as u32;
-                let version = (major, minor);
-                Ok(cmp::min(version, *MAX_CUDA_ARCH))
+        let host_arches: IndexSet<_> = device_arches
+            .into_iter()
+            .map(|version| match *MAX_CUDA_ARCH {
+                Some(max) => cmp::min(version, max),
+                None => version,
             })
-            .try_collect()?;
+            .collect();
         let mut host_arches: Vec<_> = host_arches
             .into_iter()
             .map(|(major, minor)| CudaArch {
                 major,
                 minor,
+                arch_specific: false,
                 with_ptx: false,
             })
             .collect();
@@ -49,3 +114,393 @@ pub fn cuda_arches() -> Result<&'static [CudaArch]> {
 
     Ok(arches.as_ref())
 }
+
+/// The architectures given by `TORCH_CUDA_ARCH_LIST` in sorted
+/// order.
+///
+/// If none of them is marked with `+PTX`, PTX is embedded for the
+/// newest architecture.
+pub fn torch_cuda_arches() -> &'static [CudaArch] {
+    static ARCHES: Lazy<Vec<CudaArch>> = Lazy::new(|| {
+        let mut arches: Vec<_> = TORCH_CUDA_ARCH_LIST.iter().cloned().collect();
+        arches.sort();
+        if !arches.iter().any(|arch| arch.with_ptx) {
+            if let Some(last) = arches.last_mut() {
+                last.with_ptx = true;
+            }
+        }
+        arches
+    });
+
+    &ARCHES
+}
+
+/// The architectures that libtorch CUDA libraries are compiled for,
+/// inspected by `cuobjdump`.
+///
+/// The result is not cached since it depends on `libtorch`.
+/// [CudaArchPolicy::Libtorch] caches it for the probed libtorch.
+pub fn libtorch_cuda_arches(libtorch: &Library) -> Result<Vec<CudaArch>> {
+    static REGEX_ARCH: Lazy<Regex> =
+        Lazy::new(|| Regex::new(r"\.sm_(\d+)(a)?\.(cubin|ptx)\s*$").unwrap());
+
+    let cuda_home = libtorch
+        .api
+        .cuda_home_dir()
+        .ok_or_else(|| anyhow!("CUDA is not supported by libtorch"))?;
+    let cuobjdump = cuda_home.join("bin").join("cuobjdump");
+
+    let mut arches = vec![];
+
+    for name in libtorch.cuda_libraries(true)? {
+        if !name.starts_with("torch_cuda") {
+            continue;
+        }
+        let lib_path = libtorch.lib_dir.join(format!("lib{name}.so"));
+
+        for kind in ["--list-elf", "--list-ptx"] {
+            let text = run_cuobjdump(&cuobjdump, kind, &lib_path)?;
+
+            text.lines()
+                .filter_map(|line| REGEX_ARCH.captures(line))
+                .for_each(|cap| {
+                    let number = &cap[1];
+                    let (major, minor) = number.split_at(number.len() - 1);
+                    let arch = CudaArch {
+                        major: major.parse().unwrap(),
+                        minor: minor.parse().unwrap(),
+                        arch_specific: cap.get(2).is_some(),
+                        with_ptx: &cap[3] == "ptx",
+                    };
+                    arches.push(arch);
+                });
+        }
+    }
+
+    // Merge PTX entries into cubin entries of the same version.
+    arches.sort();
+    arches.dedup_by(|next, prev| {
+        let same = next.same_target(prev);
+        if same {
+            prev.with_ptx |= next.with_ptx;
+        }
+        same
+    });
+    ensure!(
+        !arches.is_empty(),
+        "no CUDA architecture is found in libtorch"
+    );
+
+    Ok(arches)
+}
+
+/// The version of nvcc, for example `12.1.105`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct NvccVersion {
+    pub major: u32,
+    pub minor: u32,
+    pub patch: u32,
+}
+
+impl NvccVersion {
+    /// The `X.Y` release version without the patch number.
+    pub fn release(&self) -> ReleaseVersion {
+        ReleaseVersion {
+            major: self.major,
+            minor: self.minor,
+        }
+    }
+}
+
+impl fmt::Display for NvccVersion {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let Self {
+            major,
+            minor,
+            patch,
+        } = self;
+        write!(f, "{major}.{minor}.{patch}")
+    }
+}
+
+/// Get the version of the installed nvcc from `nvcc --version`.
+pub fn nvcc_version() -> Result<NvccVersion> {
+    static REGEX_VERSION: Lazy<Regex> =
+        Lazy::new(|| Regex::new(r"release \d+\.\d+, V(\d+)\.(\d+)\.(\d+)").unwrap());
+    static VERSION: OnceCell<NvccVersion> = OnceCell::new();
+
+    VERSION
+        .get_or_try_init(|| -> Result<_> {
+            let nvcc = nvcc_path();
+            let output = Command::new(&nvcc)
+                .arg("--version")
+                .output()
+                .map_err(|err| anyhow!("unable to run {}: {err}", nvcc.display()))?;
+            ensure!(
+                output.status.success(),
+                "unable to run {} --version",
+                nvcc.display()
+            );
+
+            let stdout = str::from_utf8(&output.stdout)?;
+            let cap = REGEX_VERSION
+                .captures(stdout)
+                .ok_or_else(|| anyhow!("unable to parse the output of nvcc --version\n{stdout}"))?;
+
+            Ok(NvccVersion {
+                major: cap[1].parse()?,
+                minor: cap[2].parse()?,
+                patch: cap[3].parse()?,
+            })
+        })
+        .copied()
+}
+
+/// Drop the architectures that are not supported by the installed
+/// nvcc and print a Cargo warning for each of them.
+///
+/// If an architecture embedding PTX is dropped, PTX is embedded for
+/// the newest remaining architecture instead. It fails if no
+/// architecture is left. The architectures are kept as they are if
+/// the supported architectures cannot be determined.
+pub fn filter_nvcc_cuda_arches(arches: &[CudaArch]) -> Result<Vec<CudaArch>> {
+    let supported = match supported_cuda_arches() {
+        Ok(supported) => supported,
+        Err(err) => {
+            print_cargo_warning(&format!(
+                "unable to validate CUDA architectures against nvcc: {err}"
+            ));
+            return Ok(arches.to_vec());
+        }
+    };
+    let is_supported = |arch: &CudaArch| supported.iter().any(|other| other.same_target(arch));
+
+    let (mut kept, dropped): (Vec<_>, Vec<_>) =
+        arches.iter().cloned().partition(|arch| is_supported(arch));
+
+    for arch in &dropped {
+        print_cargo_warning(&format!(
+            "CUDA architecture {arch} is not supported by {}, skipped",
+            nvcc_path().display()
+        ));
+    }
+
+    ensure!(
+        !kept.is_empty(),
+        "none of the requested CUDA architectures is supported by {}",
+        nvcc_path().display()
+    );
+
+    let dropped_ptx = dropped.iter().any(|arch| arch.with_ptx);
+    if dropped_ptx && !kept.iter().any(|arch| arch.with_ptx) {
+        kept.sort();
+        kept.last_mut().unwrap().with_ptx = true;
+    }
+
+    Ok(kept)
+}
+
+/// The path to `nvcc` in the CUDA installation, or `nvcc` in `PATH`
+/// if the installation is not found.
+pub fn nvcc_path() -> PathBuf {
+    match &*CUDA_HOME {
+        Some(dir) => dir.join("bin").join("nvcc"),
+        None => PathBuf::from("nvcc"),
+    }
+}
+
+/// The architectures supported by the installed nvcc, listed by
+/// `nvcc --list-gpu-arch`.
+pub fn nvcc_cuda_arches() -> Result<&'static [CudaArch]> {
+    static ARCHES: OnceCell<Vec<CudaArch>> = OnceCell::new();
+
+    let arches = ARCHES.get_or_try_init(|| -> Result<_> {
+        let nvcc = nvcc_path();
+        let output = Command::new(&nvcc)
+            .arg("--list-gpu-arch")
+            .output()
+            .map_err(|err| anyhow!("unable to run {}: {err}", nvcc.display()))?;
+        ensure!(
+            output.status.success(),
+            "{} is unable to list GPU architectures",
+            nvcc.display()
+        );
+
+        parse_nvcc_gpu_arches(str::from_utf8(&output.stdout)?)
+    })?;
+
+    Ok(arches)
+}
+
+/// Parse the output of `nvcc --list-gpu-arch`, which lists virtual
+/// architectures like `compute_90` and `compute_90a`. It fails if no
+/// architecture is found.
+fn parse_nvcc_gpu_arches(text: &str) -> Result<Vec<CudaArch>> {
+    let mut arches: Vec<CudaArch> = text
+        .split_whitespace()
+        .filter_map(|token| token.strip_prefix("compute_"))
+        .filter_map(|number| format!("sm_{number}").parse().ok())
+        .collect();
+    ensure!(!arches.is_empty(), "no GPU architecture is listed by nvcc");
+    arches.sort();
+    arches.dedup();
+    Ok(arches)
+}
+
+/// The architectures supported by the installed nvcc.
+///
+/// It is listed by nvcc if possible. Otherwise, it is looked up in
+/// the CUDA architecture database by the nvcc version.
+pub fn supported_cuda_arches() -> Result<Vec<CudaArch>> {
+    let err = match nvcc_cuda_arches() {
+        Ok(arches) => return Ok(arches.to_vec()),
+        Err(err) => err,
+    };
+
+    let version = nvcc_version().map_err(|_| err)?;
+    let arches = CUDA_ARCH_DB
+        .toolkit_arches(version.release())
+        .ok_or_else(|| anyhow!("CUDA {version} is not found in the CUDA architecture database"))?;
+    let mut arches = arches.to_vec();
+    arches.iter_mut().for_each(|arch| arch.with_ptx = false);
+    Ok(arches)
+}
+
+/// All architectures supported by the installed nvcc, or the
+/// configured architectures if nvcc is not available. PTX is embedded
+/// for the newest architecture. Architecture-specific variants are
+/// excluded.
+///
+/// If `major_only` is true, only `X.0` architectures are included.
+pub(crate) fn all_cuda_arches(major_only: bool) -> Vec<CudaArch> {
+    let mut arches: Vec<CudaArch> = match supported_cuda_arches() {
+        Ok(arches) => arches,
+        Err(err) => {
+            warn!("{err}, use the configured CUDA architectures instead");
+            let torch_version: Option<ReleaseVersion> = crate::env::TORCH_VERSION.parse().ok();
+            torch_version
+                .map(|version| CUDA_ARCH_DB.default_cuda_arch_list(version, None))
+                .unwrap_or_default()
+        }
+    };
+
+    arches.retain(|arch| !arch.arch_specific && (!major_only || arch.minor == 0));
+    arches.iter_mut().for_each(|arch| arch.with_ptx = false);
+    arches.sort();
+    arches.dedup();
+    if let Some(last) = arches.last_mut() {
+        last.with_ptx = true;
+    }
+    arches
+}
+
+/// The architectures of CUDA devices on the host system. It fails
+/// if no device is found.
+pub(crate) fn native_cuda_arches() -> Result<Vec<CudaArch>> {
+    let versions: IndexSet<_> = host_device_arches()?.into_iter().collect();
+    ensure!(!versions.is_empty(), "no CUDA device is detected");
+    let mut arches: Vec<_> = versions
+        .into_iter()
+        .map(|(major, minor)| CudaArch {
+            major,
+            minor,
+            arch_specific: false,
+            with_ptx: false,
+        })
+        .collect();
+    arches.sort();
+    Ok(arches)
+}
+
+fn run_cuobjdump(cuobjdump: &Path, kind: &str, lib_path: &Path) -> Result<String> {
+    let output = Command::new(cuobjdump)
+        .arg(kind)
+        .arg(lib_path)
+        .output()
+        .map_err(|err| anyhow!("unable to run {}: {err}", cuobjdump.display()))?;
+    ensure!(
+        output.status.success(),
+        "{} failed to inspect {}",
+        cuobjdump.display(),
+        lib_path.display()
+    );
+    Ok(str::from_utf8(&output.stdout)?.to_string())
+}
+
+/// Query compute capabilities of CUDA devices through the CUDA
+/// driver API.
+#[cfg(feature = "cuda-driver")]
+fn host_device_arches() -> Result<Vec<(u32, u32)>> {
+    use itertools::Itertools as _;
+    use rustacuda::{
+        device::{Device, DeviceAttribute::*},
+        CudaFlags,
+    };
+
+    rustacuda::init(CudaFlags::empty())?;
+
+    let arches = Device::devices()?
+        .map(|device| -> Result<_> {
+            let device = device?;
+            let major = device.get_attribute(ComputeCapabilityMajor)? as u32;
+            let minor = device.get_attribute(ComputeCapabilityMinor)? as u32;
+            Ok((major, minor))
+        })
+        .try_collect()?;
+    Ok(arches)
+}
+
+/// Query compute capabilities of CUDA devices through `nvidia-smi`.
+///
+/// It is used if the `cuda-driver` feature is disabled, and a warning
+/// is logged once since the CUDA driver was used before the feature
+/// became optional.
+#[cfg(not(feature = "cuda-driver"))]
+fn host_device_arches() -> Result<Vec<(u32, u32)>> {
+    static WARN: std::sync::Once = std::sync::Once::new();
+    WARN.call_once(|| {
+        warn!(
+            "the `cuda-driver` feature is disabled, detect CUDA devices by `nvidia-smi` instead \
+             of the CUDA driver"
+        );
+    });
+
+    let output = Command::new("nvidia-smi")
+        .args(["--query-gpu=compute_cap", "--format=csv,noheader"])
+        .output()
+        .map_err(|err| anyhow!("unable to run `nvidia-smi`: {err}"))?;
+    ensure!(output.status.success(), "`nvidia-smi` failed");
+
+    let arches = str::from_utf8(&output.stdout)?
+        .lines()
+        .map(|line| line.trim())
+        .filter(|line| !line.is_empty())
+        .map(|line| -> Result<_> {
+            let arch: CudaArch = line.parse()?;
+            Ok((arch.major, arch.minor))
+        })
+        .collect::<Result<_>>()?;
+    Ok(arches)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::parse_nvcc_gpu_arches;
+    use crate::config::CudaArch;
+
+    #[test]
+    fn parse_nvcc_list_gpu_arch() {
+        // nvcc --list-gpu-arch of CUDA 12.4
+        let text = "compute_50\ncompute_52\ncompute_53\ncompute_60\ncompute_61\n\
+                    compute_62\ncompute_70\ncompute_72\ncompute_75\ncompute_80\n\
+                    compute_86\ncompute_87\ncompute_89\ncompute_90\ncompute_90a\n";
+        let arches = parse_nvcc_gpu_arches(text).unwrap();
+        assert_eq!(arches.len(), 15);
+        assert_eq!(arches[0], "5.0".parse::<CudaArch>().unwrap());
+        assert!(arches.contains(&"8.6".parse().unwrap()));
+        assert_eq!(arches[14], "9.0a".parse::<CudaArch>().unwrap());
+        assert!(arches.iter().all(|arch| !arch.with_ptx));
+
+        assert!(parse_nvcc_gpu_arches("").is_err());
+    }
+}
@@ -1,44 +1,90 @@
 //! Environment variables and constatns.
 
-use crate::config::{Config, CudaArch};
+use crate::config::{Config, CudaArch, CudaArchDb, ReleaseVersion};
 use anyhow::Result;
 use cfg_if::cfg_if;
 use itertools::Itertools;
 use once_cell::sync::Lazy;
 use std::{
-    collections::{HashMap, HashSet},
+    collections::HashSet,
     env,
     ffi::{OsStr, OsString},
+    fs,
     path::{Path, PathBuf},
     process::Command,
 };
 
+/// Whether the crate runs in a build script, where Cargo sets both
+/// `OUT_DIR` and `TARGET`. `cargo run` sets `OUT_DIR` only. Cargo
+/// instructions are printed only in build scripts, so that they are
+/// not mixed into the output of binaries like `torch-bundle`.
+static IN_BUILD_SCRIPT: Lazy<bool> =
+    Lazy::new(|| env::var_os("OUT_DIR").is_some() && env::var_os("TARGET").is_some());
+
 pub(crate) static CONFIG: Lazy<Config> = Lazy::new(|| {
     let text = include_str!(concat!(env!("CARGO_MANIFEST_DIR"), "/config.toml"));
     toml::from_str(text).unwrap_or_else(|err| panic!("unable to parse config.toml\n{err}"))
 });
 
+/// The CUDA architecture database in `cuda_arch_db.toml`, merged with
+/// the file given by `TORCH_CUDA_ARCH_DB` environment variable.
+pub(crate) static CUDA_ARCH_DB: Lazy<CudaArchDb> = Lazy::new(|| {
+    let text = include_str!(concat!(env!("CARGO_MANIFEST_DIR"), "/cuda_arch_db.toml"));
+    let mut db: CudaArchDb = toml::from_str(text)
+        .unwrap_or_else(|err| panic!("unable to parse cuda_arch_db.toml\n{err}"));
+
+    if let Some(path) = rerun_env_pathbuf("TORCH_CUDA_ARCH_DB") {
+        if *IN_BUILD_SCRIPT {
+            println!("cargo:rerun-if-changed={}", path.display());
+        }
+        let text = fs::read_to_string(&path)
+            .unwrap_or_else(|err| panic!("unable to read {}: {err}", path.display()));
+        let user_db: CudaArchDb = toml::from_str(&text)
+            .unwrap_or_else(|err| panic!("unable to parse {}\n{err}", path.display()));
+        db.merge(user_db);
+    }
+
+    db
+});
+
 /// The list of CUDA architectures given by `TORCH_CUDA_ARCH_LIST` environment variable.
 ///
-/// If `TORCH_CUDA_ARCH_LIST` is not set, the default supported architectures are given.
+/// If `TORCH_CUDA_ARCH_LIST` is not set, the default architectures of
+/// the supported libtorch version are given, excluding those not
+/// supported by the installed nvcc.
 pub(crate) static TORCH_CUDA_ARCH_LIST: Lazy<HashSet<CudaArch>> = Lazy::new(|| {
     if let Some(val) = rerun_env_string("TORCH_CUDA_ARCH_LIST") {
         parse_cuda_arch_list(&val)
-            .unwrap_or_else(|_| {
+            .unwrap_or_else(|err| {
                 panic!(
-                    r#"unable to parse environment variable TORCH_CUDA_ARCH_LIST = "{}""#,
+                    r#"unable to parse environment variable TORCH_CUDA_ARCH_LIST = "{}": {err}"#,
                     val
                 );
             })
             .into_iter()
             .collect()
     } else {
-        CONFIG.torch_cuda_arch_list.clone()
+        let torch_version: ReleaseVersion = TORCH_VERSION
+            .parse()
+            .unwrap_or_else(|err| panic!("invalid torch_version in config.toml: {err}"));
+        let cuda_version = crate::cuda::nvcc_version()
+            .ok()
+            .map(|version| version.release());
+        CUDA_ARCH_DB
+            .default_cuda_arch_list(torch_version, cuda_version)
+            .into_iter()
+            .collect()
     }
 });
 
 pub(crate) static OUT_DIR: &str = env!("OUT_DIR");
 
+/// The directory containing the bundled libtch sources.
+pub(crate) static LIBTCH_DIR: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/libtch");
+
+/// The directory containing the bundled `torch_build/*.h` headers.
+pub(crate) static CSRC_INCLUDE_DIR: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/csrc/include");
+
 pub(crate) static TARGET: Lazy<Option<String>> = Lazy::new(|| rerun_env_string("TARGET"));
 
 /// The supported libtorch version.
@@ -91,15 +137,25 @@ pub static LIBTORCH_BYPASS_VERSION_CHECK: Lazy<bool> =
 pub static TORCH_CUDA_VERSION: Lazy<Option<String>> =
     Lazy::new(|| rerun_env_string("TORCH_CUDA_VERSION"));
 
+/// The list of AMD GPU architectures given by `PYTORCH_ROCM_ARCH`
+/// environment variable, for example `gfx90a;gfx942`.
+pub static PYTORCH_ROCM_ARCH: Lazy<Option<Vec<String>>> = Lazy::new(|| {
+    let val = rerun_env_string("PYTORCH_ROCM_ARCH")?;
+    let arches = val
+        .split([';', ' '])
+        .filter(|arch| !arch.is_empty())
+        .map(|arch| arch.to_string())
+        .collect();
+    Some(arches)
+});
+
 /// The value of `CUDNN_HOME` environment variable, or `CUDNN_PATH` if `CUDNN_HOME` is not set.
 pub static CUDNN_HOME: Lazy<Option<PathBuf>> =
     Lazy::new(|| rerun_env_pathbuf("CUDNN_HOME").or_else(|| rerun_env_pathbuf("CUDNN_PATH")));
 
 /// The value of `ROCM_HOME` environment variable, or `ROCM_PATH` if `ROCM_HOME` is not set.
 pub static ROCM_HOME: Lazy<Option<PathBuf>> = Lazy::new(|| {
-    let guess = rerun_env_pathbuf("ROCM_HOME")
-        .or_else(|| rerun_env_pathbuf("ROCM_PATH"))
-        .map(PathBuf::from);
+    let guess = rerun_env_pathbuf("ROCM_HOME").or_else(|| rerun_env_pathbuf("ROCM_PATH"));
 
     #[cfg(unix)]
     let guess = guess.or_else(|| {
@@ -144,9 +200,7 @@ pub static ROCM_HOME: Lazy<Option<PathBuf>> = Lazy::new(|| {
 pub static CUDA_HOME: Lazy<Option<PathBuf>> = Lazy::new(|| {
     use os_info::Type::*;
 
-    let guess = rerun_env_pathbuf("CUDA_HOME")
-        .or_else(|| rerun_env_pathbuf("CUDA_PATH"))
-        .map(PathBuf::from);
+    let guess = rerun_env_pathbuf("CUDA_HOME").or_else(|| rerun_env_pathbuf("CUDA_PATH"));
 
     #[cfg(any(target_os = "linux", target_os = "macos"))]
     let guess = guess.or_else(|| {
@@ -175,28 +229,46 @@ pub static CUDA_HOME: Lazy<Option<PathBuf>> = Lazy::new(|| {
     }
 });
 
-static CUDA_ARCH_ALIASES: Lazy<HashMap<String, Vec<CudaArch>>> =
-    Lazy::new(|| CONFIG.cuda_arch_aliases.clone());
-
-/// Parse the `;` seperated list of architecture numbers.
+/// Parse the `;` or space seperated list of architectures.
 ///
 /// For example, `3.5;3.7;5.0;5.2;5.3;6.0;6.1;6.2;7.0;7.2;7.5;8.0;8.6`.
+/// Each item is one of the following.
+///
+/// - A version `X.Y` or `X.Y+PTX`.
+/// - A nvcc spelling `sm_XY`. The PTX-only spelling `compute_XY` is
+///   rejected in favor of `X.Y+PTX`.
+/// - A family in the CUDA architecture database, such as `Ampere`.
+/// - `native` for the architectures of detected devices.
+/// - `all` for all architectures supported by the installed nvcc.
+/// - `all-major` for all `X.0` architectures supported by the
+///   installed nvcc.
 pub(crate) fn parse_cuda_arch_list(text: &str) -> Result<Vec<CudaArch>> {
     let arches: Vec<_> = text
-        .split(';')
-        .flat_map(|token| {
-            if let Some(list) = CUDA_ARCH_ALIASES.get(token) {
-                list.iter().map(|arch| Ok(arch.clone())).collect()
-            } else {
-                vec![token.parse()]
-            }
+        .split([';', ' '])
+        .map(|token| token.trim())
+        .filter(|token| !token.is_empty())
+        .map(|token| -> Result<Vec<CudaArch>> {
+            let arches = match token {
+                "native" => crate::cuda::native_cuda_arches()?,
+                "all" => crate::cuda::all_cuda_arches(false),
+                "all-major" => crate::cuda::all_cuda_arches(true),
+                _ => {
+                    if let Some(list) = CUDA_ARCH_DB.families.get(token) {
+                        list.clone()
+                    } else {
+                        vec![token.parse()?]
+                    }
+                }
+            };
+            Ok(arches)
         })
+        .flatten_ok()
         .try_collect()?;
     Ok(arches)
 }
 
 fn rerun_env(name: &str) -> Option<OsString> {
-    println!("cargo:rerun-if-env-changed={}", name);
+    print_rerun_if_env_changed(name);
     env::var_os(name)
 }
 
@@ -205,16 +277,67 @@ fn rerun_env_pathbuf(name: &str) -> Option<PathBuf> {
 }
 
 fn rerun_env_string(name: &str) -> Option<String> {
-    println!("cargo:rerun-if-env-changed={}", name);
+    print_rerun_if_env_changed(name);
     env::var(name).ok()
 }
 
+fn print_rerun_if_env_changed(name: &str) {
+    if *IN_BUILD_SCRIPT {
+        println!("cargo:rerun-if-env-changed={}", name);
+    }
+}
+
 #[cfg(test)]
 mod tests {
-    use crate::env::CONFIG;
+    use crate::{
+        config::CudaArch,
+        config::ReleaseVersion,
+        env::{parse_cuda_arch_list, CONFIG, CUDA_ARCH_DB},
+    };
 
     #[test]
     fn parse_config_toml() {
         let _ = &*CONFIG;
     }
+
+    #[test]
+    fn parse_cuda_arch_list_spellings() {
+        let arch = |major, minor, with_ptx| CudaArch {
+            major,
+            minor,
+            arch_specific: false,
+            with_ptx,
+        };
+
+        let arches = parse_cuda_arch_list("7.0 sm_86;9.0+PTX;;Pascal").unwrap();
+        assert_eq!(
+            arches,
+            [
+                arch(7, 0, false),
+                arch(8, 6, false),
+                arch(9, 0, true),
+                arch(6, 0, false),
+                arch(6, 1, true),
+            ]
+        );
+        assert!(parse_cuda_arch_list("sm_x").is_err());
+        assert!(parse_cuda_arch_list("compute_90").is_err());
+
+        let arches = parse_cuda_arch_list("sm_90a 9.0a+PTX").unwrap();
+        assert!(arches.iter().all(|arch| arch.arch_specific));
+    }
+
+    #[test]
+    fn default_cuda_arch_list() {
+        let version = |text: &str| -> ReleaseVersion { text.parse().unwrap() };
+
+        let arches = CUDA_ARCH_DB.default_cuda_arch_list(version("2.0.0"), Some(version("12.1")));
+        let first = arches.first().unwrap();
+        let last = arches.last().unwrap();
+        assert_eq!((first.major, first.minor), (5, 0));
+        assert_eq!((last.major, last.minor), (9, 0));
+
+        let arches = CUDA_ARCH_DB.default_cuda_arch_list(version("2.0.0"), None);
+        assert_eq!(arches.len(), 16);
+    }
 }
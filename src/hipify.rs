@@ -0,0 +1,391 @@
+//! Translate CUDA sources to HIP.
+//!
+//! The rules are a port of PyTorch's hipify mappings, stored in
+//! `hipify.toml`.
+
+use anyhow::{Context as _, Result};
+use once_cell::sync::Lazy;
+use regex::{Captures, Regex};
+use serde::Deserialize;
+use std::{
+    collections::{HashMap, HashSet},
+    fs,
+    path::{Component, Path, PathBuf},
+};
+
+#[derive(Debug, Clone, Deserialize)]
+struct HipifyRules {
+    namespaces: HashMap<String, String>,
+    identifiers: HashMap<String, String>,
+    headers: HashMap<String, String>,
+}
+
+static RULES: Lazy<HipifyRules> = Lazy::new(|| {
+    let text = include_str!(concat!(env!("CARGO_MANIFEST_DIR"), "/hipify.toml"));
+    toml::from_str(text).unwrap_or_else(|err| panic!("unable to parse hipify.toml\n{err}"))
+});
+
+static REGEX_INCLUDE: Lazy<Regex> =
+    Lazy::new(|| Regex::new(r#"^(\s*#\s*include\s*)([<"])([^>"]+)([>"])"#).unwrap());
+
+static REGEX_IDENTIFIER: Lazy<Regex> =
+    Lazy::new(|| Regex::new(r"(?:[A-Za-z_]\w*::)*[A-Za-z_]\w*").unwrap());
+
+/// Translate CUDA source code to HIP source code.
+///
+/// Comments and string literals are kept as they are.
+pub fn hipify_source(text: &str) -> String {
+    let mut output = String::with_capacity(text.len());
+    let mut in_comment = false;
+
+    for line in text.split_inclusive('\n') {
+        if in_comment {
+            hipify_line(line, &mut in_comment, &mut output);
+        } else if let Some(cap) = REGEX_INCLUDE.captures(line) {
+            let header = hipify_header(&cap[3]);
+            let end = cap.get(0).unwrap().end();
+            output.push_str(&cap[1]);
+            output.push_str(&cap[2]);
+            output.push_str(&header);
+            output.push_str(&cap[4]);
+            output.push_str(&line[end..]);
+        } else {
+            hipify_line(line, &mut in_comment, &mut output);
+        }
+    }
+
+    output
+}
+
+/// Translate identifiers in a line of code, skipping comments and
+/// string and character literals. `in_comment` tracks whether the
+/// line starts and ends inside a block comment.
+fn hipify_line(line: &str, in_comment: &mut bool, output: &mut String) {
+    let push_code = |output: &mut String, code: &str| {
+        let code = REGEX_IDENTIFIER.replace_all(code, |cap: &Captures| hipify_identifier(&cap[0]));
+        output.push_str(&code);
+    };
+
+    let bytes = line.as_bytes();
+    let mut start = 0;
+    let mut index = 0;
+
+    while index < bytes.len() {
+        if *in_comment {
+            match line[index..].find("*/") {
+                Some(offset) => {
+                    index += offset + 2;
+                    output.push_str(&line[start..index]);
+                    start = index;
+                    *in_comment = false;
+                }
+                None => {
+                    output.push_str(&line[start..]);
+                    return;
+                }
+            }
+            continue;
+        }
+
+        match (bytes[index], bytes.get(index + 1)) {
+            (b'/', Some(b'/')) => {
+                push_code(output, &line[start..index]);
+                output.push_str(&line[index..]);
+                return;
+            }
+            (b'/', Some(b'*')) => {
+                push_code(output, &line[start..index]);
+                start = index;
+                index += 2;
+                *in_comment = true;
+            }
+            (quote @ (b'"' | b'\''), _) => {
+                push_code(output, &line[start..index]);
+                let end = literal_end(line, index, quote);
+                output.push_str(&line[index..end]);
+                start = end;
+                index = end;
+            }
+            _ => index += 1,
+        }
+    }
+
+    push_code(output, &line[start..]);
+}
+
+/// Find the end of the string or character literal starting at
+/// `begin`. It returns the line length if the literal is not closed.
+fn literal_end(line: &str, begin: usize, quote: u8) -> usize {
+    let mut chars = line[begin + 1..].char_indices();
+
+    while let Some((offset, c)) = chars.next() {
+        match c {
+            '\\' => {
+                chars.next();
+            }
+            c if c == quote as char => return begin + 1 + offset + 1,
+            _ => {}
+        }
+    }
+
+    line.len()
+}
+
+/// Translate a CUDA source file to HIP and write it to `dst`.
+pub fn hipify_file<P, Q>(src: P, dst: Q) -> Result<()>
+where
+    P: AsRef<Path>,
+    Q: AsRef<Path>,
+{
+    let src = src.as_ref();
+    let dst = dst.as_ref();
+
+    let text =
+        fs::read_to_string(src).with_context(|| format!("unable to read {}", src.display()))?;
+    write_if_changed(dst, &hipify_source(&text))
+}
+
+/// Translate a source file and the local headers it includes to HIP
+/// under `dst_root`.
+///
+/// Each file is written to `dst_root` joined with its absolute path,
+/// so that headers included relative to the including file, like
+/// `#include "common.cuh"` or `#include "../common.cuh"`, resolve to
+/// the translated copies. Such headers are followed transitively. The
+/// translated source is renamed with the `extension`.
+///
+/// It returns the path of the translated source and the original
+/// paths of the included headers.
+pub fn hipify_file_tree<P, Q>(
+    src: P,
+    dst_root: Q,
+    extension: &str,
+) -> Result<(PathBuf, Vec<PathBuf>)>
+where
+    P: AsRef<Path>,
+    Q: AsRef<Path>,
+{
+    let src = src.as_ref();
+    let dst_root = dst_root.as_ref();
+
+    let src =
+        fs::canonicalize(src).with_context(|| format!("unable to resolve {}", src.display()))?;
+    let dst = mirror_path(dst_root, &src).with_extension(extension);
+
+    let mut headers = vec![];
+    let mut visited = HashSet::from([src.clone()]);
+    let mut queue = vec![src.clone()];
+
+    while let Some(file) = queue.pop() {
+        let text = fs::read_to_string(&file)
+            .with_context(|| format!("unable to read {}", file.display()))?;
+        let file_dst = if file == src {
+            dst.clone()
+        } else {
+            mirror_path(dst_root, &file)
+        };
+        write_if_changed(&file_dst, &hipify_source(&text))?;
+
+        let dir = file.parent().unwrap_or_else(|| Path::new("/"));
+        for line in text.lines() {
+            let Some(cap) = REGEX_INCLUDE.captures(line) else {
+                continue;
+            };
+            if &cap[2] != "\"" {
+                continue;
+            }
+
+            let header = dir.join(&cap[3]);
+            if !header.is_file() {
+                continue;
+            }
+            let header = fs::canonicalize(&header)?;
+            if visited.insert(header.clone()) {
+                headers.push(header.clone());
+                queue.push(header);
+            }
+        }
+    }
+
+    Ok((dst, headers))
+}
+
+/// Translate all source and header files in `src_dir` to HIP and
+/// write them to `dst_dir` with the same directory structure. It
+/// returns the paths of written files.
+pub fn hipify_dir<P, Q>(src_dir: P, dst_dir: Q) -> Result<Vec<PathBuf>>
+where
+    P: AsRef<Path>,
+    Q: AsRef<Path>,
+{
+    const EXTENSIONS: &[&str] = &[
+        "h", "hh", "hpp", "hxx", "cuh", "inl", "c", "cc", "cpp", "cu",
+    ];
+
+    let src_dir = src_dir.as_ref();
+    let dst_dir = dst_dir.as_ref();
+    let mut outputs = vec![];
+
+    for entry in fs::read_dir(src_dir)? {
+        let entry = entry?;
+        let src = entry.path();
+        let dst = dst_dir.join(entry.file_name());
+
+        if entry.file_type()?.is_dir() {
+            outputs.extend(hipify_dir(&src, &dst)?);
+            continue;
+        }
+
+        let is_source = src
+            .extension()
+            .and_then(|ext| ext.to_str())
+            .map(|ext| EXTENSIONS.contains(&ext))
+            .unwrap_or(false);
+        if is_source {
+            hipify_file(&src, &dst)?;
+            outputs.push(dst);
+        }
+    }
+
+    Ok(outputs)
+}
+
+/// Get the path of `path` mirrored under `root`.
+fn mirror_path(root: &Path, path: &Path) -> PathBuf {
+    let relative: PathBuf = path
+        .components()
+        .filter(|component| matches!(component, Component::Normal(_)))
+        .collect();
+    root.join(relative)
+}
+
+fn write_if_changed(dst: &Path, text: &str) -> Result<()> {
+    if let Some(dir) = dst.parent() {
+        fs::create_dir_all(dir)?;
+    }
+
+    // Keep the modification time of the output if nothing changes.
+    if fs::read_to_string(dst).ok().as_deref() != Some(text) {
+        fs::write(dst, text).with_context(|| format!("unable to write {}", dst.display()))?;
+    }
+
+    Ok(())
+}
+
+fn hipify_header(header: &str) -> String {
+    if let Some(to) = RULES.headers.get(header) {
+        return to.clone();
+    }
+
+    for dir in ["ATen/cuda/", "c10/cuda/"] {
+        if header.starts_with(dir) {
+            return header.replace("cuda", "hip").replace("CUDA", "HIP");
+        }
+    }
+
+    header.to_string()
+}
+
+fn hipify_identifier(ident: &str) -> String {
+    if let Some(to) = RULES.identifiers.get(ident) {
+        return to.clone();
+    }
+
+    let mut segments: Vec<String> = ident.split("::").map(|s| s.to_string()).collect();
+    let last = segments.len() - 1;
+
+    for (index, segment) in segments.iter_mut().enumerate() {
+        if index < last {
+            if let Some(to) = RULES.namespaces.get(segment.as_str()) {
+                *segment = to.clone();
+            }
+        } else if let Some(to) = RULES.identifiers.get(segment.as_str()) {
+            *segment = to.clone();
+        }
+    }
+
+    segments.join("::")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{hipify_file_tree, hipify_source};
+    use std::fs;
+
+    #[test]
+    fn hipify_kernel_source() {
+        let cuda = r#"#include <cuda_runtime.h>
+#include <ATen/cuda/CUDAContext.h>
+#include <c10/cuda/CUDAGuard.h>
+#include "my_kernel.cuh"
+
+void launch(float *ptr, size_t size) {
+    cudaStream_t stream = at::cuda::getCurrentCUDAStream();
+    cudaMemsetAsync(ptr, 0, size, stream);
+    cub::DeviceReduce::Sum(nullptr, size, ptr, ptr, 1);
+    C10_CUDA_KERNEL_LAUNCH_CHECK();
+}
+"#;
+        let hip = r#"#include <hip/hip_runtime.h>
+#include <ATen/hip/HIPContext.h>
+#include <ATen/hip/impl/HIPGuardImplMasqueradingAsCUDA.h>
+#include "my_kernel.cuh"
+
+void launch(float *ptr, size_t size) {
+    hipStream_t stream = at::hip::getCurrentHIPStreamMasqueradingAsCUDA();
+    hipMemsetAsync(ptr, 0, size, stream);
+    hipcub::DeviceReduce::Sum(nullptr, size, ptr, ptr, 1);
+    C10_HIP_KERNEL_LAUNCH_CHECK();
+}
+"#;
+        assert_eq!(hipify_source(cuda), hip);
+    }
+
+    #[test]
+    fn keep_user_symbols_comments_and_strings() {
+        let cuda = r#"// cudaMalloc is called by cuda_forward
+void cuda_forward(float *ptr, cudaStream_t stream);
+void cudaHelper(); /* cudaFree
+   cudaMemcpy */ cudaError_t err = cudaGetLastError();
+const char *msg = "cudaMalloc failed: \"cudaFree\""; char c = '"'; cudaFree(ptr);
+"#;
+        let hip = r#"// cudaMalloc is called by cuda_forward
+void cuda_forward(float *ptr, hipStream_t stream);
+void cudaHelper(); /* cudaFree
+   cudaMemcpy */ hipError_t err = hipGetLastError();
+const char *msg = "cudaMalloc failed: \"cudaFree\""; char c = '"'; hipFree(ptr);
+"#;
+        assert_eq!(hipify_source(cuda), hip);
+    }
+
+    #[test]
+    fn hipify_relative_includes() {
+        let dir = std::env::temp_dir().join(format!("hipify-tree-{}", std::process::id()));
+        let src_dir = dir.join("proj").join("csrc");
+        let dst_root = dir.join("out");
+        fs::create_dir_all(&src_dir).unwrap();
+        fs::write(
+            src_dir.join("kernel.cu"),
+            "#include \"sibling.cuh\"\n#include \"../common.cuh\"\n#include <missing.h>\n",
+        )
+        .unwrap();
+        fs::write(src_dir.join("sibling.cuh"), "cudaStream_t stream;\n").unwrap();
+        fs::write(dir.join("proj").join("common.cuh"), "cudaError_t err;\n").unwrap();
+
+        let (dst, headers) = hipify_file_tree(src_dir.join("kernel.cu"), &dst_root, "hip").unwrap();
+        assert_eq!(dst.extension().unwrap(), "hip");
+        assert_eq!(headers.len(), 2);
+
+        let dst_dir = dst.parent().unwrap();
+        assert_eq!(
+            fs::read_to_string(dst_dir.join("sibling.cuh")).unwrap(),
+            "hipStream_t stream;\n"
+        );
+        assert_eq!(
+            fs::read_to_string(dst_dir.join("../common.cuh")).unwrap(),
+            "hipError_t err;\n"
+        );
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
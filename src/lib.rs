@@ -1,16 +1,24 @@
 //! Utilities to link libtorch FFI interface.
 
 mod build;
+#[cfg(target_os = "linux")]
+pub mod bundle;
 pub mod config;
 pub mod cuda;
 #[cfg(feature = "download-libtorch")]
 mod download;
 pub mod env;
+pub mod hipify;
 pub mod library;
+pub mod libtch_gen;
 mod probe;
+#[cfg(feature = "runtime")]
+pub mod runtime;
 mod utils;
 
 pub use build::*;
+#[cfg(target_os = "linux")]
+pub use bundle::*;
 pub use config::*;
 pub use cuda::*;
 #[cfg(feature = "download-libtorch")]
@@ -1,7 +1,11 @@
 //! Libtorch installation and capabilities.
 
-use crate::{env::TARGET, utils::IteratorExt as _};
-use anyhow::{bail, Result};
+use crate::{
+    cuda::NvccVersion,
+    env::{LIBTCH_DIR, TARGET},
+    utils::IteratorExt as _,
+};
+use anyhow::{bail, ensure, Result};
 use cfg_if::cfg_if;
 use itertools::chain;
 use std::{
@@ -61,10 +65,12 @@ impl Library {
                 Api::Cuda(CudaApi {
                     cuda_home,
                     cudnn_home,
+                    ..
                 })
                 | Api::CudaSplit(CudaSplitApi {
                     cuda_home,
                     cudnn_home,
+                    ..
                 }) => {
                     let cuda_include = cuda_home.join("include");
                     let cudnn_include = cudnn_home.map(|path| path.join("include"));
@@ -109,10 +115,12 @@ impl Library {
                 Api::Cuda(CudaApi {
                     cuda_home,
                     cudnn_home,
+                    ..
                 })
                 | Api::CudaSplit(CudaSplitApi {
                     cuda_home,
                     cudnn_home,
+                    ..
                 }) => {
                     cfg_if! {
                         if #[cfg(target_os = "windows")] {
@@ -170,13 +178,33 @@ impl Library {
         &self,
         use_cuda_api: impl Into<Option<bool>>,
         use_python: bool,
+    ) -> Result<impl Iterator<Item = &'static str>> {
+        let base_libraries = ["c10", "torch_cpu", "torch"];
+        let python_library = use_python.then_some("torch_python");
+        let cuda_libraries = self.cuda_libraries(use_cuda_api)?;
+
+        let gomp = TARGET.as_ref().and_then(|target| {
+            let ok = !target.contains("msvc") && !target.contains("apple");
+            ok.then_some("gomp")
+        });
+
+        Ok(chain!(base_libraries, python_library, cuda_libraries, gomp))
+    }
+
+    /// Generate linked CUDA libraries that is passed to C++ compiler.
+    ///
+    /// The `use_cuda_api` determines whether to enable CUDA API.
+    /// - `true`: CUDA is mandatory.
+    /// - `false`: CUDA is disabled.
+    /// - `None`: CUDA is enabled if supported.
+    pub fn cuda_libraries(
+        &self,
+        use_cuda_api: impl Into<Option<bool>>,
     ) -> Result<impl Iterator<Item = &'static str>> {
         let Self { api, .. } = self;
         let use_cuda_api = use_cuda_api
             .into()
             .unwrap_or_else(|| self.is_cuda_api_available());
-        let base_libraries = ["c10", "torch_cpu", "torch"];
-        let python_library = use_python.then_some("torch_python");
         let base_cuda_libraries = ["cudart", "c10_cuda"];
 
         let cuda_libraries = if use_cuda_api {
@@ -202,12 +230,66 @@ impl Library {
             iter::empty().boxed()
         };
 
-        let gomp = TARGET.as_ref().and_then(|target| {
-            let ok = !target.contains("msvc") && !target.contains("apple");
-            ok.then_some("gomp")
-        });
+        Ok(cuda_libraries)
+    }
 
-        Ok(chain!(base_libraries, python_library, cuda_libraries, gomp))
+    /// Generate linker arguments that force linking to CUDA libraries.
+    ///
+    /// Linkers with `--as-needed` drop libraries whose symbols are
+    /// not referenced directly, for example `libtorch_cuda.so`. The
+    /// returned arguments wrap the CUDA libraries in
+    /// `-Wl,--push-state,--no-as-needed` and `-Wl,--pop-state` so that
+    /// they are always kept, while the linker state of later
+    /// arguments is restored. It returns no arguments if CUDA is
+    /// disabled.
+    ///
+    /// The `use_cuda_api` determines whether to enable CUDA API.
+    /// - `true`: CUDA is mandatory.
+    /// - `false`: CUDA is disabled.
+    /// - `None`: CUDA is enabled if supported.
+    pub fn cuda_link_args(&self, use_cuda_api: impl Into<Option<bool>>) -> Result<Vec<String>> {
+        let libraries: Vec<_> = self
+            .cuda_libraries(use_cuda_api)?
+            .map(|lib| format!("-l{lib}"))
+            .collect();
+
+        if libraries.is_empty() {
+            return Ok(vec![]);
+        }
+
+        let args = chain!(
+            ["-Wl,--push-state,--no-as-needed".to_string()],
+            libraries,
+            ["-Wl,--pop-state".to_string()]
+        )
+        .collect();
+        Ok(args)
+    }
+
+    /// Get the C++ source file that defines the `dummy_cuda_dependency()` function.
+    ///
+    /// If CUDA is enabled, the function references symbols in
+    /// libtorch CUDA libraries. Otherwise, it is a no-op function.
+    ///
+    /// The `use_cuda_api` determines whether to enable CUDA API.
+    /// - `true`: CUDA is mandatory.
+    /// - `false`: CUDA is disabled.
+    /// - `None`: CUDA is enabled if supported.
+    pub fn cuda_dependency_source(&self, use_cuda_api: impl Into<Option<bool>>) -> Result<PathBuf> {
+        let use_cuda_api = use_cuda_api
+            .into()
+            .unwrap_or_else(|| self.is_cuda_api_available());
+        ensure!(
+            !use_cuda_api || self.is_cuda_api_available(),
+            "CUDA runtime is not available"
+        );
+
+        let name = if use_cuda_api {
+            "dummy_cuda_dependency.cpp"
+        } else {
+            "fake_cuda_dependency.cpp"
+        };
+        Ok(Path::new(LIBTCH_DIR).join(name))
     }
 
     /// Check if CUDA runtime is available.
@@ -247,6 +329,16 @@ impl Api {
         }
     }
 
+    /// The version of nvcc in the CUDA installation, if it can be
+    /// determined.
+    pub fn nvcc_version(&self) -> Option<NvccVersion> {
+        match self {
+            Self::None | Self::Hip(_) => None,
+            Self::Cuda(api) => api.nvcc_version,
+            Self::CudaSplit(api) => api.nvcc_version,
+        }
+    }
+
     pub fn cuda_include_dir(&self) -> Option<PathBuf> {
         Some(self.cuda_home_dir()?.join("include"))
     }
@@ -283,19 +375,24 @@ impl From<CudaSplitApi> for Api {
 }
 
 #[derive(Debug, Clone, PartialEq, Eq, Hash)]
+#[non_exhaustive]
 pub struct HipApi {
     pub rocm_home: &'static Path,
     pub miopen_home: &'static Path,
 }
 
 #[derive(Debug, Clone, PartialEq, Eq, Hash)]
+#[non_exhaustive]
 pub struct CudaApi {
     pub cuda_home: &'static Path,
     pub cudnn_home: Option<&'static Path>,
+    pub nvcc_version: Option<NvccVersion>,
 }
 
 #[derive(Debug, Clone, PartialEq, Eq, Hash)]
+#[non_exhaustive]
 pub struct CudaSplitApi {
     pub cuda_home: &'static Path,
     pub cudnn_home: Option<&'static Path>,
+    pub nvcc_version: Option<NvccVersion>,
 }
@@ -0,0 +1,786 @@
+//! Generate the op wrappers of libtch from libtorch's
+//! `Declarations.yaml`.
+//!
+//! The generator is a port of tch's `gen/gen.ml`. It produces the C++
+//! wrappers `torch_api_generated.cpp.h`, the C header
+//! `torch_api_generated.h` and the matching Rust declarations
+//! `torch_api_generated.rs`. The ops to skip are listed in
+//! `libtch_gen.toml`.
+//!
+//! An [OpFilter] restricts the wrappers to a subset of ops.
+//!
+//! Only `Declarations.yaml` is supported. `native_functions.yaml` lacks
+//! the resolved C++ types the generator relies on.
+
+use crate::{build::utils::print_cargo_warning, probe_libtorch};
+use anyhow::{bail, Context as _, Result};
+use itertools::Itertools as _;
+use once_cell::sync::Lazy;
+use serde::Deserialize;
+use std::{
+    collections::{BTreeMap, HashSet},
+    fs,
+    path::{Path, PathBuf},
+};
+
+const HEADER: &str = "// THIS FILE IS AUTOMATICALLY GENERATED, DO NOT EDIT BY HAND!\n\n";
+
+#[derive(Debug, Clone, Deserialize)]
+struct GenRules {
+    excluded_functions: HashSet<String>,
+    excluded_prefixes: Vec<String>,
+    excluded_suffixes: Vec<String>,
+    no_tensor_options: HashSet<String>,
+}
+
+static RULES: Lazy<GenRules> = Lazy::new(|| {
+    let text = include_str!(concat!(env!("CARGO_MANIFEST_DIR"), "/libtch_gen.toml"));
+    toml::from_str(text).unwrap_or_else(|err| panic!("unable to parse libtch_gen.toml\n{err}"))
+});
+
+/// An entry in `Declarations.yaml`.
+#[derive(Debug, Clone, Deserialize)]
+struct Declaration {
+    name: String,
+    operator_name: String,
+    overload_name: String,
+    #[serde(default)]
+    deprecated: bool,
+    #[serde(default)]
+    method_of: Vec<String>,
+    #[serde(default)]
+    arguments: Vec<DeclArgument>,
+    #[serde(default)]
+    returns: Vec<DeclReturn>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+struct DeclArgument {
+    name: String,
+    dynamic_type: String,
+    #[serde(default)]
+    is_nullable: bool,
+    #[serde(default)]
+    default: Option<serde_yaml::Value>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+struct DeclReturn {
+    dynamic_type: String,
+}
+
+/// Select the ops to wrap by their ATen function names, for example
+/// `add`, `add_` and `add_out`. All overloads of a selected function
+/// are wrapped.
+///
+/// Without an allowlist, all ops except the denied ones are wrapped.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct OpFilter {
+    allowlist: Option<HashSet<String>>,
+    denylist: HashSet<String>,
+}
+
+impl OpFilter {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn allow_op<S>(&mut self, name: S) -> &mut Self
+    where
+        S: AsRef<str>,
+    {
+        self.allow_ops([name])
+    }
+
+    pub fn allow_ops<S>(&mut self, names: S) -> &mut Self
+    where
+        S: IntoIterator,
+        S::Item: AsRef<str>,
+    {
+        self.allowlist
+            .get_or_insert_with(HashSet::new)
+            .extend(names.into_iter().map(|name| name.as_ref().to_owned()));
+        self
+    }
+
+    pub fn deny_op<S>(&mut self, name: S) -> &mut Self
+    where
+        S: AsRef<str>,
+    {
+        self.deny_ops([name])
+    }
+
+    pub fn deny_ops<S>(&mut self, names: S) -> &mut Self
+    where
+        S: IntoIterator,
+        S::Item: AsRef<str>,
+    {
+        self.denylist
+            .extend(names.into_iter().map(|name| name.as_ref().to_owned()));
+        self
+    }
+
+    /// Returns `true` if all ops are selected.
+    pub fn is_empty(&self) -> bool {
+        self.allowlist.is_none() && self.denylist.is_empty()
+    }
+
+    pub fn is_allowed(&self, name: &str) -> bool {
+        let allowed = match &self.allowlist {
+            Some(allowlist) => allowlist.contains(name),
+            None => true,
+        };
+        allowed && !self.denylist.contains(name)
+    }
+}
+
+/// Read a file of op names, one per line. Blank lines and text after
+/// `#` are ignored.
+pub fn read_op_list<P>(path: P) -> Result<Vec<String>>
+where
+    P: AsRef<Path>,
+{
+    let path = path.as_ref();
+    let text =
+        fs::read_to_string(path).with_context(|| format!("unable to read {}", path.display()))?;
+    let names = text
+        .lines()
+        .map(|line| line.split('#').next().unwrap().trim())
+        .filter(|name| !name.is_empty())
+        .map(|name| name.to_string())
+        .collect();
+    Ok(names)
+}
+
+/// The generated sources of libtch op wrappers.
+#[derive(Debug, Clone)]
+pub struct GeneratedOps {
+    /// The content of `torch_api_generated.cpp.h`.
+    pub cpp: String,
+    /// The content of `torch_api_generated.h`.
+    pub header: String,
+    /// The content of `torch_api_generated.rs`.
+    pub rust: String,
+}
+
+impl GeneratedOps {
+    /// Generate the op wrappers selected by `filter` from the text of
+    /// `Declarations.yaml`.
+    pub fn from_declarations(text: &str, filter: &OpFilter) -> Result<Self> {
+        let decls: Vec<Declaration> =
+            serde_yaml::from_str(text).context("unable to parse Declarations.yaml")?;
+
+        // Filter after naming, so that the exported names do not
+        // depend on the selected ops.
+        let mut funcs = name_functions(decls.iter().filter_map(Func::new))?;
+        funcs.retain(|_, func| filter.is_allowed(&func.name));
+
+        if let Some(allowlist) = &filter.allowlist {
+            let missing = allowlist
+                .iter()
+                .filter(|name| !funcs.values().any(|func| &&func.name == name))
+                .sorted()
+                .join(", ");
+            if !missing.is_empty() {
+                print_cargo_warning(&format!("no op wrappers are generated for {missing}"));
+            }
+        }
+
+        let mut cpp = String::from(HEADER);
+        let mut header = String::from(HEADER);
+        let mut rust = String::from(HEADER);
+
+        rust.push_str("extern \"C\" {\n");
+        for (name, func) in &funcs {
+            cpp.push_str(&func.cpp_wrapper(name));
+            header.push_str(&func.c_declaration(name));
+            rust.push_str(&func.rust_declaration(name));
+        }
+        rust.push_str("}\n");
+
+        Ok(Self { cpp, header, rust })
+    }
+
+    /// Write the sources to `dir`. Files are left untouched if their
+    /// contents do not change.
+    pub fn write_to_dir<P>(&self, dir: P) -> Result<()>
+    where
+        P: AsRef<Path>,
+    {
+        let dir = dir.as_ref();
+        fs::create_dir_all(dir)?;
+
+        for (file_name, text) in [
+            ("torch_api_generated.cpp.h", &self.cpp),
+            ("torch_api_generated.h", &self.header),
+            ("torch_api_generated.rs", &self.rust),
+        ] {
+            let path = dir.join(file_name);
+            if fs::read_to_string(&path).ok().as_deref() != Some(text.as_str()) {
+                fs::write(&path, text)
+                    .with_context(|| format!("unable to write {}", path.display()))?;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// Generate the libtch op wrappers selected by `filter` from the
+/// `Declarations.yaml` at `declarations` and write them to `out_dir`.
+pub fn generate_libtch_ops<P, Q>(declarations: P, out_dir: Q, filter: &OpFilter) -> Result<()>
+where
+    P: AsRef<Path>,
+    Q: AsRef<Path>,
+{
+    let declarations = declarations.as_ref();
+    let text = fs::read_to_string(declarations)
+        .with_context(|| format!("unable to read {}", declarations.display()))?;
+    GeneratedOps::from_declarations(&text, filter)?.write_to_dir(out_dir)
+}
+
+/// Locate `share/ATen/Declarations.yaml` in the probed libtorch.
+pub fn libtorch_declarations() -> Result<PathBuf> {
+    let libtorch = probe_libtorch()?;
+    let path = libtorch
+        .lib_dir
+        .parent()
+        .map(|dir| dir.join("share").join("ATen").join("Declarations.yaml"));
+    match path {
+        Some(path) if path.is_file() => Ok(path),
+        _ => bail!(
+            "unable to find share/ATen/Declarations.yaml in libtorch at {}",
+            libtorch.lib_dir.display()
+        ),
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum ArgType {
+    Bool,
+    Int64,
+    Int64Option,
+    Double,
+    DoubleOption,
+    Tensor,
+    TensorOption,
+    IntList,
+    IntListOption,
+    DoubleList,
+    TensorOptList,
+    TensorList,
+    TensorOptions,
+    Scalar,
+    ScalarType,
+    Device,
+    String,
+}
+
+impl ArgType {
+    fn new(dynamic_type: &str, is_nullable: bool) -> Option<Self> {
+        let ty = match dynamic_type.to_lowercase().as_str() {
+            "bool" => Self::Bool,
+            "int64_t" | "c10::symint" if is_nullable => Self::Int64Option,
+            "int64_t" | "c10::symint" => Self::Int64,
+            "double" if is_nullable => Self::DoubleOption,
+            "double" => Self::Double,
+            "at::tensor" if is_nullable => Self::TensorOption,
+            "at::tensor" => Self::Tensor,
+            "at::tensoroptions" => Self::TensorOptions,
+            "at::intarrayref" | "c10::symintarrayref" if is_nullable => Self::IntListOption,
+            "at::intarrayref" | "c10::symintarrayref" => Self::IntList,
+            "at::optionalintarrayref" | "at::optionalsymintarrayref" => Self::IntListOption,
+            "at::arrayref<double>" => Self::DoubleList,
+            "const c10::list<c10::optional<at::tensor>> &" => Self::TensorOptList,
+            "at::tensorlist" => Self::TensorList,
+            "at::device" => Self::Device,
+            "const at::scalar &" | "at::scalar" => Self::Scalar,
+            "at::scalartype" => Self::ScalarType,
+            "c10::string_view" => Self::String,
+            _ => return None,
+        };
+        Some(ty)
+    }
+}
+
+/// The C types in the signatures of the wrappers.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum CType {
+    Int,
+    Int64,
+    Double,
+    Uint8,
+    Tensor,
+    Scalar,
+    Int64Ptr,
+    DoublePtr,
+    TensorPtr,
+    CharPtr,
+}
+
+impl CType {
+    fn c_param(self, name: &str) -> String {
+        match self {
+            Self::Int => format!("int {name}"),
+            Self::Int64 => format!("int64_t {name}"),
+            Self::Double => format!("double {name}"),
+            Self::Uint8 => format!("uint8_t {name}"),
+            Self::Tensor => format!("tensor {name}"),
+            Self::Scalar => format!("scalar {name}"),
+            Self::Int64Ptr => format!("int64_t *{name}"),
+            Self::DoublePtr => format!("double *{name}"),
+            Self::TensorPtr => format!("tensor *{name}"),
+            Self::CharPtr => format!("char* {name}"),
+        }
+    }
+
+    fn rust_type(self) -> &'static str {
+        match self {
+            Self::Int => "::std::os::raw::c_int",
+            Self::Int64 => "i64",
+            Self::Double => "f64",
+            Self::Uint8 => "u8",
+            Self::Tensor | Self::Scalar => "*mut ::std::os::raw::c_void",
+            Self::Int64Ptr => "*const i64",
+            Self::DoublePtr => "*const f64",
+            Self::TensorPtr => "*const *mut ::std::os::raw::c_void",
+            Self::CharPtr => "*const ::std::os::raw::c_char",
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+struct Arg {
+    name: String,
+    ty: ArgType,
+}
+
+impl Arg {
+    /// The C parameters the argument expands to.
+    fn c_params(&self) -> Vec<(CType, String)> {
+        let name = &self.name;
+        let pair = |first: CType, first_suffix: &str, second: CType, second_suffix: &str| {
+            vec![
+                (first, format!("{name}_{first_suffix}")),
+                (second, format!("{name}_{second_suffix}")),
+            ]
+        };
+
+        match self.ty {
+            ArgType::IntList | ArgType::IntListOption => {
+                pair(CType::Int64Ptr, "data", CType::Int, "len")
+            }
+            ArgType::DoubleList => pair(CType::DoublePtr, "data", CType::Int, "len"),
+            ArgType::TensorList | ArgType::TensorOptList => {
+                pair(CType::TensorPtr, "data", CType::Int, "len")
+            }
+            ArgType::TensorOptions => pair(CType::Int, "kind", CType::Int, "device"),
+            ArgType::String => pair(CType::CharPtr, "ptr", CType::Int, "len"),
+            ArgType::Int64Option => pair(CType::Int64, "v", CType::Uint8, "null"),
+            ArgType::DoubleOption => pair(CType::Double, "v", CType::Uint8, "null"),
+            ArgType::Bool | ArgType::ScalarType | ArgType::Device => {
+                vec![(CType::Int, name.clone())]
+            }
+            ArgType::Int64 => vec![(CType::Int64, name.clone())],
+            ArgType::Double => vec![(CType::Double, name.clone())],
+            ArgType::Tensor | ArgType::TensorOption => vec![(CType::Tensor, name.clone())],
+            ArgType::Scalar => vec![(CType::Scalar, name.clone())],
+        }
+    }
+
+    /// The C++ expression that converts the C parameters to the
+    /// argument of the libtorch call.
+    fn cpp_arg(&self) -> String {
+        let name = &self.name;
+        match self.ty {
+            ArgType::Tensor | ArgType::Scalar => format!("*{name}"),
+            ArgType::TensorOption => format!("({name} ? *{name} : torch::Tensor())"),
+            ArgType::Bool => format!("(bool){name}"),
+            ArgType::IntList => format!("torch::IntArrayRef({name}_data, {name}_len)"),
+            ArgType::IntListOption => format!(
+                "{name}_data == nullptr ? c10::nullopt : \
+                 c10::optional<torch::IntArrayRef>(torch::IntArrayRef({name}_data, {name}_len))"
+            ),
+            ArgType::DoubleList => format!("at::ArrayRef<double>({name}_data, {name}_len)"),
+            ArgType::String => format!("std::string({name}_ptr, {name}_len)"),
+            ArgType::TensorOptList => format!("of_carray_tensor_opt({name}_data, {name}_len)"),
+            ArgType::TensorList => format!("of_carray_tensor({name}_data, {name}_len)"),
+            ArgType::TensorOptions => format!(
+                "at::device(device_of_int({name}_device)).dtype(at::ScalarType({name}_kind))"
+            ),
+            ArgType::Int64Option => {
+                format!("{name}_null ? c10::nullopt : c10::optional<int64_t>({name}_v)")
+            }
+            ArgType::DoubleOption => {
+                format!("{name}_null ? c10::nullopt : c10::optional<double>({name}_v)")
+            }
+            ArgType::ScalarType => format!("at::ScalarType({name})"),
+            ArgType::Device => format!("device_of_int({name})"),
+            ArgType::Int64 | ArgType::Double => name.clone(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Returns {
+    /// A fixed number of tensors.
+    Fixed(usize),
+    /// A list of tensors.
+    Dynamic,
+    Bool,
+    Int64,
+    Double,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Kind {
+    Function,
+    Method,
+}
+
+#[derive(Debug, Clone)]
+struct Func {
+    name: String,
+    operator_name: String,
+    overload_name: String,
+    args: Vec<Arg>,
+    returns: Returns,
+    kind: Kind,
+}
+
+impl Func {
+    /// Convert a declaration into a wrappable function. It returns
+    /// `None` if the op is excluded or has unsupported types.
+    fn new(decl: &Declaration) -> Option<Self> {
+        let name = &decl.name;
+        let excluded = decl.deprecated
+            || RULES.excluded_functions.contains(name)
+            || RULES
+                .excluded_prefixes
+                .iter()
+                .any(|prefix| name.starts_with(prefix))
+            || RULES
+                .excluded_suffixes
+                .iter()
+                .any(|suffix| name.ends_with(suffix));
+        if excluded {
+            return None;
+        }
+
+        let returns = if decl.returns.iter().all(|r| r.dynamic_type == "at::Tensor") {
+            // Ops returning nothing cannot be wrapped.
+            if decl.returns.is_empty() {
+                return None;
+            }
+            Returns::Fixed(decl.returns.len())
+        } else {
+            match decl.returns.as_slice() {
+                [ret] => match ret.dynamic_type.as_str() {
+                    "at::TensorList" => Returns::Dynamic,
+                    "bool" => Returns::Bool,
+                    "int64_t" => Returns::Int64,
+                    "double" => Returns::Double,
+                    _ => return None,
+                },
+                _ => return None,
+            }
+        };
+
+        let kind = if decl.method_of.iter().any(|m| m == "namespace") {
+            Kind::Function
+        } else if decl.method_of.iter().any(|m| m == "Tensor") {
+            Kind::Method
+        } else {
+            return None;
+        };
+
+        let mut args = vec![];
+        for arg in &decl.arguments {
+            let has_default = arg.default.is_some();
+            let Some(ty) = ArgType::new(&arg.dynamic_type, arg.is_nullable) else {
+                // Unsupported arguments with default values are
+                // omitted from the call.
+                if has_default {
+                    continue;
+                }
+                return None;
+            };
+
+            match ty {
+                ArgType::Scalar if has_default && !arg.is_nullable => continue,
+                ArgType::TensorOptions if has_default && RULES.no_tensor_options.contains(name) => {
+                    continue
+                }
+                _ => {}
+            }
+
+            let arg_name = if arg.name == "self" && ty == ArgType::Scalar {
+                "self_scalar".to_string()
+            } else {
+                arg.name.clone()
+            };
+            args.push(Arg { name: arg_name, ty });
+        }
+
+        if kind == Kind::Method && args.first().map(|arg| arg.ty) != Some(ArgType::Tensor) {
+            return None;
+        }
+
+        Some(Self {
+            name: name.clone(),
+            operator_name: decl.operator_name.to_lowercase(),
+            overload_name: decl.overload_name.to_lowercase(),
+            args,
+            returns,
+            kind,
+        })
+    }
+
+    fn c_params(&self) -> Vec<(CType, String)> {
+        self.args.iter().flat_map(Arg::c_params).collect()
+    }
+
+    fn c_param_list(&self) -> String {
+        self.c_params()
+            .into_iter()
+            .map(|(ty, name)| ty.c_param(&name))
+            .join(", ")
+    }
+
+    fn cpp_call(&self) -> String {
+        match self.kind {
+            Kind::Function => {
+                let args = self.args.iter().map(Arg::cpp_arg).join(", ");
+                format!("torch::{}({args})", self.name)
+            }
+            Kind::Method => {
+                let (first, rest) = self.args.split_first().unwrap();
+                let args = rest.iter().map(Arg::cpp_arg).join(", ");
+                format!("{}->{}({args})", first.name, self.name)
+            }
+        }
+    }
+
+    fn cpp_wrapper(&self, name: &str) -> String {
+        let params = self.c_param_list();
+        let call = self.cpp_call();
+
+        match self.returns {
+            Returns::Fixed(count) => {
+                let params = prepend("tensor *out__", &params);
+                let outputs: String = if count == 1 {
+                    "    out__[0] = new torch::Tensor(outputs__);\n".to_string()
+                } else {
+                    (0..count)
+                        .map(|i| {
+                            format!("    out__[{i}] = new torch::Tensor(std::get<{i}>(outputs__));\n")
+                        })
+                        .collect()
+                };
+                format!(
+                    "void atg_{name}({params}) {{\n  PROTECT(\n    auto outputs__ = {call};\n{outputs}  )\n}}\n\n"
+                )
+            }
+            Returns::Dynamic => format!(
+                "tensor *atg_{name}({params}) {{\n  PROTECT(\n    auto outputs__ = {call};\n    \
+                 int sz = outputs__.size();\n    \
+                 torch::Tensor **out__ = (torch::Tensor**)malloc((sz + 1) * sizeof(torch::Tensor*));\n    \
+                 for (int i = 0; i < sz; ++i)\n      \
+                 out__[i] = new torch::Tensor(outputs__[i]);\n    \
+                 out__[sz] = nullptr;\n    \
+                 return out__;\n  )\n  return nullptr;\n}}\n\n"
+            ),
+            Returns::Bool | Returns::Int64 | Returns::Double => {
+                let ty = self.c_return_type();
+                format!(
+                    "{ty} atg_{name}({params}) {{\n  PROTECT(\n    return {call};\n  )\n  return 0;\n}}\n\n"
+                )
+            }
+        }
+    }
+
+    fn c_declaration(&self, name: &str) -> String {
+        let params = self.c_param_list();
+        match self.returns {
+            Returns::Fixed(_) => format!("void atg_{name}({});\n", prepend("tensor *", &params)),
+            Returns::Dynamic => format!("tensor *atg_{name}({params});\n"),
+            Returns::Bool | Returns::Int64 | Returns::Double => {
+                format!("{} atg_{name}({params});\n", self.c_return_type())
+            }
+        }
+    }
+
+    fn rust_declaration(&self, name: &str) -> String {
+        let mut params: Vec<String> = self
+            .c_params()
+            .into_iter()
+            .map(|(ty, name)| format!("{}: {}", rust_ident(&name), ty.rust_type()))
+            .collect();
+
+        let ret = match self.returns {
+            Returns::Fixed(_) => {
+                params.insert(0, "out__: *mut *mut ::std::os::raw::c_void".to_string());
+                ""
+            }
+            Returns::Dynamic => " -> *mut *mut ::std::os::raw::c_void",
+            Returns::Bool => " -> ::std::os::raw::c_int",
+            Returns::Int64 => " -> i64",
+            Returns::Double => " -> f64",
+        };
+        format!("    pub fn atg_{name}({}){ret};\n", params.join(", "))
+    }
+
+    fn c_return_type(&self) -> &'static str {
+        match self.returns {
+            Returns::Bool => "int",
+            Returns::Int64 => "int64_t",
+            Returns::Double => "double",
+            Returns::Fixed(_) | Returns::Dynamic => "tensor *",
+        }
+    }
+}
+
+/// Assign exported names to functions. Overloads of the same operator
+/// are disambiguated by their overload names, where the shortest
+/// overload keeps the operator name.
+fn name_functions<I>(funcs: I) -> Result<BTreeMap<String, Func>>
+where
+    I: IntoIterator<Item = Func>,
+{
+    let mut groups: BTreeMap<String, Vec<Func>> = BTreeMap::new();
+    for func in funcs {
+        groups
+            .entry(func.operator_name.clone())
+            .or_default()
+            .push(func);
+    }
+
+    let mut named = BTreeMap::new();
+    for (operator_name, mut funcs) in groups {
+        if funcs.len() == 1 {
+            named.insert(operator_name, funcs.pop().unwrap());
+            continue;
+        }
+
+        let has_empty_overload = funcs.iter().any(|f| f.overload_name.is_empty());
+        funcs.sort_by_key(|f| (f.name.len(), f.args.len()));
+
+        for (index, func) in funcs.into_iter().enumerate() {
+            let overload_name = &func.overload_name;
+            let name = if overload_name.is_empty() || (index == 0 && !has_empty_overload) {
+                operator_name.clone()
+            } else if operator_name.ends_with('_') {
+                format!("{operator_name}{overload_name}_")
+            } else {
+                format!("{operator_name}_{overload_name}")
+            };
+
+            if named.contains_key(&name) {
+                bail!("duplicated op wrapper name atg_{name}");
+            }
+            named.insert(name, func);
+        }
+    }
+
+    Ok(named)
+}
+
+fn prepend(first: &str, rest: &str) -> String {
+    if rest.is_empty() {
+        first.to_string()
+    } else {
+        format!("{first}, {rest}")
+    }
+}
+
+/// Escape parameter names that are Rust keywords.
+fn rust_ident(name: &str) -> String {
+    const KEYWORDS: &[&str] = &[
+        "as", "box", "break", "const", "continue", "crate", "dyn", "else", "enum", "extern",
+        "false", "final", "fn", "for", "if", "impl", "in", "let", "loop", "match", "mod", "move",
+        "mut", "override", "pub", "ref", "return", "self", "static", "struct", "super", "trait",
+        "true", "type", "unsafe", "use", "where", "while",
+    ];
+
+    if KEYWORDS.contains(&name) {
+        format!("{name}_")
+    } else {
+        name.to_string()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{GeneratedOps, OpFilter};
+
+    #[test]
+    fn generate_overloaded_ops() {
+        let yaml = r#"
+- name: __and__
+  operator_name: __and__
+  overload_name: Scalar
+  method_of: [Type, Tensor, namespace]
+  arguments:
+  - {name: self, dynamic_type: at::Tensor, is_nullable: false}
+  - {name: other, dynamic_type: const at::Scalar &, is_nullable: false}
+  returns:
+  - {dynamic_type: at::Tensor}
+- name: __and__
+  operator_name: __and__
+  overload_name: Tensor
+  method_of: [Type, Tensor, namespace]
+  arguments:
+  - {name: self, dynamic_type: at::Tensor, is_nullable: false}
+  - {name: other, dynamic_type: at::Tensor, is_nullable: false}
+  returns:
+  - {dynamic_type: at::Tensor}
+- name: zeros
+  operator_name: zeros
+  overload_name: ''
+  method_of: [Type, namespace]
+  arguments:
+  - {name: size, dynamic_type: at::IntArrayRef, is_nullable: false}
+  - {name: options, dynamic_type: at::TensorOptions, is_nullable: false, default: '{}'}
+  returns:
+  - {dynamic_type: at::Tensor}
+- name: backward
+  operator_name: backward
+  overload_name: ''
+  method_of: [Type, Tensor]
+  arguments:
+  - {name: self, dynamic_type: at::Tensor, is_nullable: false}
+  returns: []
+"#;
+        let ops = GeneratedOps::from_declarations(yaml, &OpFilter::new()).unwrap();
+
+        assert_eq!(
+            ops.header,
+            "// THIS FILE IS AUTOMATICALLY GENERATED, DO NOT EDIT BY HAND!\n\n\
+             void atg___and__(tensor *, tensor self, scalar other);\n\
+             void atg___and__tensor_(tensor *, tensor self, tensor other);\n\
+             void atg_zeros(tensor *, int64_t *size_data, int size_len, int options_kind, int options_device);\n"
+        );
+        assert!(ops.cpp.contains(
+            "void atg_zeros(tensor *out__, int64_t *size_data, int size_len, int options_kind, int options_device) {\n  \
+             PROTECT(\n    \
+             auto outputs__ = torch::zeros(torch::IntArrayRef(size_data, size_len), at::device(device_of_int(options_device)).dtype(at::ScalarType(options_kind)));\n    \
+             out__[0] = new torch::Tensor(outputs__);\n  \
+             )\n}\n"
+        ));
+        assert!(ops.rust.contains(
+            "    pub fn atg___and__(out__: *mut *mut ::std::os::raw::c_void, self_: *mut ::std::os::raw::c_void, other: *mut ::std::os::raw::c_void);\n"
+        ));
+        assert!(!ops.cpp.contains("backward"));
+
+        // Filtering keeps the names of the remaining overloads.
+        let mut filter = OpFilter::new();
+        filter.allow_ops(["__and__", "zeros"]).deny_op("zeros");
+        let ops = GeneratedOps::from_declarations(yaml, &filter).unwrap();
+        assert_eq!(
+            ops.header,
+            "// THIS FILE IS AUTOMATICALLY GENERATED, DO NOT EDIT BY HAND!\n\n\
+             void atg___and__(tensor *, tensor self, scalar other);\n\
+             void atg___and__tensor_(tensor *, tensor self, tensor other);\n"
+        );
+    }
+}
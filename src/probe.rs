@@ -38,6 +38,12 @@ pub(crate) struct ProbePython {
     pub libraries: Vec<String>,
 }
 
+pub(crate) struct ProbePythonExtension {
+    pub ext_suffix: String,
+    pub include: PathBuf,
+    pub pybind11_abi: Vec<(String, String)>,
+}
+
 /// Probe the installation directory of libtorch and its capabilities.
 pub fn probe_libtorch() -> Result<&'static Library> {
     static PROBE: OnceCell<Library> = OnceCell::new();
@@ -104,7 +110,7 @@ fn probe_libtorch_private() -> Result<Library> {
 /// 2. The host system is Linux and `/usr/lib/libtorch.so` exists.
 /// 3. `LIBTORCH_USE_PYTORCH` environment variable is set and the PyTorch is found.
 /// 4. If `download-libtorch` feature is set, download from the URL generated by
-///   [libtorch_url()](crate::download::libtorch_url) and returns the extracted directory.
+///    [libtorch_url()](crate::download::libtorch_url) and returns the extracted directory.
 ///
 /// The function is idempotent. It only run once even when the
 /// function is called multiple times.
@@ -154,7 +160,7 @@ pub(crate) fn probe_cxx11_abi() -> bool {
             Path::new(OUT_DIR)
                 .join("use_cxx11_abi")
                 .exists()
-        } else if #[cfg(target_os = "window")] {
+        } else if #[cfg(target_os = "windows")] {
             // TODO: check _MSVC_LANG
             true
         } else {
@@ -229,6 +235,52 @@ pub(crate) fn probe_python() -> Result<ProbePython> {
     })
 }
 
+/// Probe the Python interpreter for the file name suffix of
+/// extension modules, the Python include directory and the pybind11
+/// ABI macros that PyTorch is built with.
+pub(crate) fn probe_python_extension() -> Result<ProbePythonExtension> {
+    const PYTHON_PROBE_EXTENSION_CODE: &str = include_str!(concat!(
+        env!("CARGO_MANIFEST_DIR"),
+        "/pysrc/probe_python_extension.py"
+    ));
+
+    let python_interpreter = find_python_interpreter()?;
+    let output = Command::new(python_interpreter)
+        .arg("-c")
+        .arg(PYTHON_PROBE_EXTENSION_CODE)
+        .output()
+        .with_context(|| format!("error running {python_interpreter:?}"))?;
+
+    let mut ext_suffix = None;
+    let mut include = None;
+    let mut pybind11_abi = vec![];
+
+    for line in output.stdout.lines() {
+        let line = line?;
+
+        if let Some(suffix) = line.strip_prefix("PYTHON_EXT_SUFFIX: ") {
+            ext_suffix = Some(suffix.to_string());
+        } else if let Some(path) = line.strip_prefix("PYTHON_INCLUDE: ") {
+            include = Some(PathBuf::from(path));
+        } else if let Some((key, value)) = line.split_once(": ") {
+            if key.starts_with("PYBIND11_") {
+                pybind11_abi.push((key.to_string(), value.to_string()));
+            }
+        }
+    }
+
+    let ext_suffix =
+        ext_suffix.ok_or_else(|| anyhow!("no PYTHON_EXT_SUFFIX returned by python {output:?}"))?;
+    let include =
+        include.ok_or_else(|| anyhow!("no PYTHON_INCLUDE returned by python {output:?}"))?;
+
+    Ok(ProbePythonExtension {
+        ext_suffix,
+        include,
+        pybind11_abi,
+    })
+}
+
 fn probe_pytorch() -> Result<ProbePyTorch> {
     const PYTHON_PROBE_PYTORCH_CODE: &str = include_str!(concat!(
         env!("CARGO_MANIFEST_DIR"),
@@ -301,16 +353,26 @@ fn probe_cuda_api(lib_dir: &Path) -> Api {
         }
         .into()
     } else if let Some(cuda_home) = &*CUDA_HOME {
+        let nvcc_version = match crate::cuda::nvcc_version() {
+            Ok(version) => Some(version),
+            Err(err) => {
+                warn!("unable to determine nvcc version: {err}");
+                None
+            }
+        };
+
         if probe_library_file("torch_cuda_cu") && probe_library_file("torch_cuda_cpp") {
             CudaSplitApi {
                 cuda_home,
                 cudnn_home: CUDNN_HOME.as_deref(),
+                nvcc_version,
             }
             .into()
         } else if probe_library_file("torch_cuda") {
             CudaApi {
                 cuda_home,
                 cudnn_home: CUDNN_HOME.as_deref(),
+                nvcc_version,
             }
             .into()
         } else {
@@ -0,0 +1,32 @@
+//! Safe wrappers over the libtch C API.
+//!
+//! The wrappers own the `tensor`, `scalar`, `module`, `optimizer` and
+//! `ivalue` handles of `torch_api.h` and free them on drop. Errors
+//! raised by libtorch are returned as [anyhow::Error].
+//!
+//! The symbols are resolved against the libtch library built by
+//! [TchApi](crate::TchApi), which must be linked into the final
+//! artifact.
+
+pub use device::*;
+mod device;
+
+pub use ivalue::*;
+mod ivalue;
+
+pub use kind::*;
+mod kind;
+
+pub use module::*;
+mod module;
+
+pub use optimizer::*;
+mod optimizer;
+
+pub use scalar::*;
+mod scalar;
+
+pub use tensor::*;
+mod tensor;
+
+mod ffi;
@@ -0,0 +1,42 @@
+use anyhow::{bail, Result};
+use std::os::raw::c_int;
+
+/// The device of a tensor or module.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Device {
+    Cpu,
+    Cuda(usize),
+}
+
+impl Device {
+    pub(super) fn to_c_int(self) -> c_int {
+        match self {
+            Self::Cpu => -1,
+            Self::Cuda(index) => index as c_int,
+        }
+    }
+
+    pub(super) fn of_c_int(device: c_int) -> Result<Self> {
+        Ok(match device {
+            -1 => Self::Cpu,
+            index if index >= 0 => Self::Cuda(index as usize),
+            _ => bail!("unsupported device"),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Device;
+
+    #[test]
+    fn device_of_c_int() {
+        assert_eq!(Device::of_c_int(-1).unwrap(), Device::Cpu);
+        assert_eq!(Device::of_c_int(0).unwrap(), Device::Cuda(0));
+        assert_eq!(Device::of_c_int(3).unwrap(), Device::Cuda(3));
+        assert!(Device::of_c_int(-2).is_err());
+        for device in [Device::Cpu, Device::Cuda(1)] {
+            assert_eq!(Device::of_c_int(device.to_c_int()).unwrap(), device);
+        }
+    }
+}
@@ -0,0 +1,174 @@
+//! Declarations of the libtch C API used by the runtime wrappers.
+
+use anyhow::{anyhow, Result};
+use std::{
+    ffi::CStr,
+    os::raw::{c_char, c_int, c_void},
+};
+
+pub(super) type CTensor = *mut c_void;
+pub(super) type CScalar = *mut c_void;
+pub(super) type CModule = *mut c_void;
+pub(super) type COptimizer = *mut c_void;
+pub(super) type CIValue = *mut c_void;
+
+extern "C" {
+    fn free(ptr: *mut c_void);
+
+    fn get_and_reset_last_err() -> *mut c_char;
+
+    pub(super) fn at_new_tensor() -> CTensor;
+    pub(super) fn at_tensor_of_data(
+        vs: *const c_void,
+        dims: *const i64,
+        ndims: usize,
+        element_size_in_bytes: usize,
+        kind: c_int,
+    ) -> CTensor;
+    pub(super) fn at_copy_data(
+        tensor: CTensor,
+        vs: *mut c_void,
+        numel: usize,
+        element_size_in_bytes: usize,
+    );
+    pub(super) fn at_shallow_clone(tensor: CTensor) -> CTensor;
+    pub(super) fn at_defined(tensor: CTensor) -> c_int;
+    pub(super) fn at_device(tensor: CTensor) -> c_int;
+    pub(super) fn at_dim(tensor: CTensor) -> usize;
+    pub(super) fn at_shape(tensor: CTensor, dims: *mut i64);
+    pub(super) fn at_scalar_type(tensor: CTensor) -> c_int;
+    pub(super) fn at_requires_grad(tensor: CTensor) -> c_int;
+    pub(super) fn at_backward(tensor: CTensor, keep_graph: c_int, create_graph: c_int);
+    pub(super) fn at_to_string(tensor: CTensor, line_size: c_int) -> *mut c_char;
+    pub(super) fn at_free(tensor: CTensor);
+
+    pub(super) fn ats_int(v: i64) -> CScalar;
+    pub(super) fn ats_float(v: f64) -> CScalar;
+    pub(super) fn ats_to_int(scalar: CScalar) -> i64;
+    pub(super) fn ats_to_float(scalar: CScalar) -> f64;
+    pub(super) fn ats_to_string(scalar: CScalar) -> *mut c_char;
+    pub(super) fn ats_free(scalar: CScalar);
+
+    pub(super) fn ato_adam(lr: f64, beta1: f64, beta2: f64, weight_decay: f64) -> COptimizer;
+    pub(super) fn ato_adamw(lr: f64, beta1: f64, beta2: f64, weight_decay: f64) -> COptimizer;
+    pub(super) fn ato_rms_prop(
+        lr: f64,
+        alpha: f64,
+        eps: f64,
+        weight_decay: f64,
+        momentum: f64,
+        centered: c_int,
+    ) -> COptimizer;
+    pub(super) fn ato_sgd(
+        lr: f64,
+        momentum: f64,
+        dampening: f64,
+        weight_decay: f64,
+        nesterov: c_int,
+    ) -> COptimizer;
+    pub(super) fn ato_add_parameters(optimizer: COptimizer, tensor: CTensor, group: usize);
+    pub(super) fn ato_set_learning_rate(optimizer: COptimizer, lr: f64);
+    pub(super) fn ato_set_learning_rate_group(optimizer: COptimizer, group: usize, lr: f64);
+    pub(super) fn ato_set_momentum(optimizer: COptimizer, momentum: f64);
+    pub(super) fn ato_set_momentum_group(optimizer: COptimizer, group: usize, momentum: f64);
+    pub(super) fn ato_set_weight_decay(optimizer: COptimizer, weight_decay: f64);
+    pub(super) fn ato_set_weight_decay_group(
+        optimizer: COptimizer,
+        group: usize,
+        weight_decay: f64,
+    );
+    pub(super) fn ato_zero_grad(optimizer: COptimizer);
+    pub(super) fn ato_step(optimizer: COptimizer);
+    pub(super) fn ato_free(optimizer: COptimizer);
+
+    pub(super) fn atm_load(filename: *mut c_char) -> CModule;
+    pub(super) fn atm_load_on_device(filename: *mut c_char, device: c_int) -> CModule;
+    pub(super) fn atm_load_str(data: *mut c_char, size: usize) -> CModule;
+    pub(super) fn atm_load_str_on_device(data: *mut c_char, size: usize, device: c_int) -> CModule;
+    pub(super) fn atm_forward(module: CModule, tensors: *const CTensor, ntensors: c_int)
+        -> CTensor;
+    pub(super) fn atm_forward_(
+        module: CModule,
+        ivalues: *const CIValue,
+        nivalues: c_int,
+    ) -> CIValue;
+    pub(super) fn atm_method(
+        module: CModule,
+        method_name: *mut c_char,
+        tensors: *const CTensor,
+        ntensors: c_int,
+    ) -> CTensor;
+    pub(super) fn atm_method_(
+        module: CModule,
+        method_name: *mut c_char,
+        ivalues: *const CIValue,
+        nivalues: c_int,
+    ) -> CIValue;
+    pub(super) fn atm_eval(module: CModule);
+    pub(super) fn atm_train(module: CModule);
+    pub(super) fn atm_to(module: CModule, device: c_int, kind: c_int, non_blocking: bool);
+    pub(super) fn atm_save(module: CModule, filename: *mut c_char);
+    pub(super) fn atm_named_parameters(
+        module: CModule,
+        data: *mut c_void,
+        f: extern "C" fn(*mut c_void, *mut c_char, CTensor),
+    );
+    pub(super) fn atm_free(module: CModule);
+
+    pub(super) fn ati_none() -> CIValue;
+    pub(super) fn ati_tensor(tensor: CTensor) -> CIValue;
+    pub(super) fn ati_int(v: i64) -> CIValue;
+    pub(super) fn ati_double(v: f64) -> CIValue;
+    pub(super) fn ati_bool(v: c_int) -> CIValue;
+    pub(super) fn ati_string(v: *mut c_char) -> CIValue;
+    pub(super) fn ati_tuple(values: *mut CIValue, len: c_int) -> CIValue;
+    pub(super) fn ati_generic_list(values: *mut CIValue, len: c_int) -> CIValue;
+    pub(super) fn ati_generic_dict(values: *mut CIValue, len: c_int) -> CIValue;
+    pub(super) fn ati_int_list(values: *mut i64, len: c_int) -> CIValue;
+    pub(super) fn ati_double_list(values: *mut f64, len: c_int) -> CIValue;
+    pub(super) fn ati_bool_list(values: *mut c_char, len: c_int) -> CIValue;
+    pub(super) fn ati_tensor_list(values: *mut CTensor, len: c_int) -> CIValue;
+
+    pub(super) fn ati_to_tensor(ivalue: CIValue) -> CTensor;
+    pub(super) fn ati_to_int(ivalue: CIValue) -> i64;
+    pub(super) fn ati_to_double(ivalue: CIValue) -> f64;
+    pub(super) fn ati_to_string(ivalue: CIValue) -> *mut c_char;
+    pub(super) fn ati_to_bool(ivalue: CIValue) -> c_int;
+    pub(super) fn ati_length(ivalue: CIValue) -> c_int;
+    pub(super) fn ati_tuple_length(ivalue: CIValue) -> c_int;
+    pub(super) fn ati_to_tuple(ivalue: CIValue, outputs: *mut CIValue, len: c_int);
+    pub(super) fn ati_to_generic_list(ivalue: CIValue, outputs: *mut CIValue, len: c_int);
+    pub(super) fn ati_to_generic_dict(ivalue: CIValue, outputs: *mut CIValue, len: c_int);
+    pub(super) fn ati_to_int_list(ivalue: CIValue, outputs: *mut i64, len: c_int);
+    pub(super) fn ati_to_double_list(ivalue: CIValue, outputs: *mut f64, len: c_int);
+    pub(super) fn ati_to_bool_list(ivalue: CIValue, outputs: *mut c_char, len: c_int);
+    pub(super) fn ati_to_tensor_list(ivalue: CIValue, outputs: *mut CTensor, len: c_int);
+    pub(super) fn ati_tag(ivalue: CIValue) -> c_int;
+    pub(super) fn ati_free(ivalue: CIValue);
+}
+
+/// Return the error libtch recorded during the last call, if any.
+pub(super) fn last_err() -> Result<()> {
+    let err = unsafe { get_and_reset_last_err() };
+    if err.is_null() {
+        Ok(())
+    } else {
+        Err(anyhow!(unsafe { take_string(err) }))
+    }
+}
+
+/// Pass the result of a libtch call through, or return the error
+/// libtch recorded during the call.
+pub(super) fn check<T>(value: T) -> Result<T> {
+    last_err().map(|()| value)
+}
+
+/// Copy a string allocated by libtch and free it.
+///
+/// # Safety
+/// `ptr` must be a non-null string allocated by `strdup()`.
+pub(super) unsafe fn take_string(ptr: *mut c_char) -> String {
+    let text = CStr::from_ptr(ptr).to_string_lossy().into_owned();
+    free(ptr as *mut c_void);
+    text
+}
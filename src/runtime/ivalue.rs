@@ -0,0 +1,281 @@
+use super::{
+    ffi::{self, check, last_err, take_string, CIValue, CTensor},
+    Tensor,
+};
+use anyhow::{anyhow, bail, Error, Result};
+use std::{
+    ffi::CString,
+    os::raw::{c_char, c_int},
+    ptr,
+};
+
+/// A TorchScript value passed to and returned from modules.
+#[derive(Debug)]
+pub enum IValue {
+    None,
+    Tensor(Tensor),
+    Double(f64),
+    Int(i64),
+    Bool(bool),
+    Tuple(Vec<IValue>),
+    IntList(Vec<i64>),
+    DoubleList(Vec<f64>),
+    BoolList(Vec<bool>),
+    String(String),
+    TensorList(Vec<Tensor>),
+    GenericList(Vec<IValue>),
+    GenericDict(Vec<(IValue, IValue)>),
+}
+
+/// An owned `torch::jit::IValue` handle.
+pub(super) struct RawIValue {
+    ptr: CIValue,
+}
+
+impl RawIValue {
+    /// Take the ownership of the handle returned by a libtch call.
+    pub(super) fn new(ptr: CIValue) -> Result<Self> {
+        let ptr = check(ptr)?;
+        Ok(Self { ptr })
+    }
+
+    pub(super) fn as_ptr(&self) -> CIValue {
+        self.ptr
+    }
+}
+
+impl Drop for RawIValue {
+    fn drop(&mut self) {
+        unsafe { ffi::ati_free(self.ptr) }
+    }
+}
+
+impl IValue {
+    /// Convert the value to a libtch handle. Tensors are shared with
+    /// the handle.
+    pub(super) fn to_raw(&self) -> Result<RawIValue> {
+        let ptr = unsafe {
+            match self {
+                Self::None => ffi::ati_none(),
+                Self::Tensor(tensor) => ffi::ati_tensor(tensor.as_ptr()),
+                Self::Double(value) => ffi::ati_double(*value),
+                Self::Int(value) => ffi::ati_int(*value),
+                Self::Bool(value) => ffi::ati_bool(*value as c_int),
+                Self::Tuple(values) => {
+                    let raws = to_raws(values.iter())?;
+                    let mut ptrs: Vec<_> = raws.iter().map(RawIValue::as_ptr).collect();
+                    ffi::ati_tuple(ptrs.as_mut_ptr(), len(&ptrs)?)
+                }
+                Self::GenericList(values) => {
+                    let raws = to_raws(values.iter())?;
+                    let mut ptrs: Vec<_> = raws.iter().map(RawIValue::as_ptr).collect();
+                    ffi::ati_generic_list(ptrs.as_mut_ptr(), len(&ptrs)?)
+                }
+                Self::GenericDict(entries) => {
+                    let raws = to_raws(entries.iter().flat_map(|(key, value)| [key, value]))?;
+                    let mut ptrs: Vec<_> = raws.iter().map(RawIValue::as_ptr).collect();
+                    ffi::ati_generic_dict(ptrs.as_mut_ptr(), len(entries)?)
+                }
+                Self::IntList(values) => {
+                    let mut values = values.clone();
+                    ffi::ati_int_list(values.as_mut_ptr(), len(&values)?)
+                }
+                Self::DoubleList(values) => {
+                    let mut values = values.clone();
+                    ffi::ati_double_list(values.as_mut_ptr(), len(&values)?)
+                }
+                Self::BoolList(values) => {
+                    let mut values: Vec<c_char> = values.iter().map(|&v| v as c_char).collect();
+                    ffi::ati_bool_list(values.as_mut_ptr(), len(&values)?)
+                }
+                Self::String(value) => {
+                    let value = CString::new(value.as_str())?;
+                    ffi::ati_string(value.as_ptr() as *mut c_char)
+                }
+                Self::TensorList(tensors) => {
+                    let mut ptrs: Vec<_> = tensors.iter().map(Tensor::as_ptr).collect();
+                    ffi::ati_tensor_list(ptrs.as_mut_ptr(), len(&ptrs)?)
+                }
+            }
+        };
+        RawIValue::new(ptr)
+    }
+
+    /// Convert a libtch handle to a value.
+    pub(super) fn from_raw(raw: &RawIValue) -> Result<Self> {
+        let ptr = raw.as_ptr();
+        let tag = check(unsafe { ffi::ati_tag(ptr) })?;
+
+        let value = unsafe {
+            match tag {
+                0 => Self::None,
+                1 => Self::Tensor(Tensor::from_raw(check(ffi::ati_to_tensor(ptr))?)),
+                2 => Self::Double(check(ffi::ati_to_double(ptr))?),
+                3 => Self::Int(check(ffi::ati_to_int(ptr))?),
+                4 => Self::Bool(check(ffi::ati_to_bool(ptr))? != 0),
+                5 => {
+                    let len = check(ffi::ati_tuple_length(ptr))?;
+                    let mut ptrs = vec![ptr::null_mut(); len as usize];
+                    ffi::ati_to_tuple(ptr, ptrs.as_mut_ptr(), len);
+                    let raws = take_raws(ptrs);
+                    last_err()?;
+                    Self::Tuple(from_raws(&raws)?)
+                }
+                6 => {
+                    let len = check(ffi::ati_length(ptr))?;
+                    let mut values = vec![0; len as usize];
+                    ffi::ati_to_int_list(ptr, values.as_mut_ptr(), len);
+                    last_err()?;
+                    Self::IntList(values)
+                }
+                7 => {
+                    let len = check(ffi::ati_length(ptr))?;
+                    let mut values = vec![0.0; len as usize];
+                    ffi::ati_to_double_list(ptr, values.as_mut_ptr(), len);
+                    last_err()?;
+                    Self::DoubleList(values)
+                }
+                8 => {
+                    let len = check(ffi::ati_length(ptr))?;
+                    let mut values: Vec<c_char> = vec![0; len as usize];
+                    ffi::ati_to_bool_list(ptr, values.as_mut_ptr(), len);
+                    last_err()?;
+                    Self::BoolList(values.into_iter().map(|v| v != 0).collect())
+                }
+                9 => {
+                    let text = check(ffi::ati_to_string(ptr))?;
+                    if text.is_null() {
+                        bail!("unable to read the string value");
+                    }
+                    Self::String(take_string(text))
+                }
+                10 => {
+                    let len = check(ffi::ati_length(ptr))?;
+                    let mut ptrs: Vec<CTensor> = vec![ptr::null_mut(); len as usize];
+                    ffi::ati_to_tensor_list(ptr, ptrs.as_mut_ptr(), len);
+                    let tensors: Vec<_> = ptrs.into_iter().map(|p| Tensor::from_raw(p)).collect();
+                    last_err()?;
+                    Self::TensorList(tensors)
+                }
+                12 => {
+                    let len = check(ffi::ati_length(ptr))?;
+                    let mut ptrs = vec![ptr::null_mut(); len as usize];
+                    ffi::ati_to_generic_list(ptr, ptrs.as_mut_ptr(), len);
+                    let raws = take_raws(ptrs);
+                    last_err()?;
+                    Self::GenericList(from_raws(&raws)?)
+                }
+                13 => {
+                    let len = check(ffi::ati_length(ptr))?;
+                    let mut ptrs = vec![ptr::null_mut(); 2 * len as usize];
+                    ffi::ati_to_generic_dict(ptr, ptrs.as_mut_ptr(), len);
+                    let raws = take_raws(ptrs);
+                    last_err()?;
+                    let mut values = from_raws(&raws)?.into_iter();
+                    let mut entries = vec![];
+                    while let (Some(key), Some(value)) = (values.next(), values.next()) {
+                        entries.push((key, value));
+                    }
+                    Self::GenericDict(entries)
+                }
+                _ => bail!("unsupported IValue tag {tag}"),
+            }
+        };
+        Ok(value)
+    }
+}
+
+fn to_raws<'a, I>(values: I) -> Result<Vec<RawIValue>>
+where
+    I: Iterator<Item = &'a IValue>,
+{
+    values.map(IValue::to_raw).collect()
+}
+
+/// Take the ownership of the handles filled by a libtch call before
+/// checking its error, so that a partial fill is freed as well. Null
+/// handles are skipped by `ati_free()`.
+fn take_raws(ptrs: Vec<CIValue>) -> Vec<RawIValue> {
+    ptrs.into_iter().map(|ptr| RawIValue { ptr }).collect()
+}
+
+fn from_raws(raws: &[RawIValue]) -> Result<Vec<IValue>> {
+    raws.iter().map(IValue::from_raw).collect()
+}
+
+fn len<T>(values: &[T]) -> Result<c_int> {
+    c_int::try_from(values.len()).map_err(|_| anyhow!("too many values"))
+}
+
+macro_rules! impl_from {
+    ($ty:ty, $variant:ident) => {
+        impl From<$ty> for IValue {
+            fn from(value: $ty) -> Self {
+                Self::$variant(value)
+            }
+        }
+
+        impl TryFrom<IValue> for $ty {
+            type Error = Error;
+
+            fn try_from(value: IValue) -> Result<Self> {
+                match value {
+                    IValue::$variant(value) => Ok(value),
+                    value => bail!("expect {}, but get {value:?}", stringify!($variant)),
+                }
+            }
+        }
+    };
+}
+
+impl_from!(Tensor, Tensor);
+impl_from!(f64, Double);
+impl_from!(i64, Int);
+impl_from!(bool, Bool);
+impl_from!(Vec<i64>, IntList);
+impl_from!(Vec<f64>, DoubleList);
+impl_from!(Vec<bool>, BoolList);
+impl_from!(String, String);
+impl_from!(Vec<Tensor>, TensorList);
+
+impl From<&str> for IValue {
+    fn from(value: &str) -> Self {
+        Self::String(value.to_string())
+    }
+}
+
+impl From<()> for IValue {
+    fn from(_: ()) -> Self {
+        Self::None
+    }
+}
+
+// Dropping an IValue frees libtch handles, so the tests link against
+// libtch. Run them with
+// `RUSTFLAGS="--cfg libtch_linked -L <dir> -l tch -l torch -l c10"`.
+#[cfg(all(test, libtch_linked))]
+mod tests {
+    use super::IValue;
+
+    #[test]
+    fn ivalue_conversions() {
+        assert_eq!(i64::try_from(IValue::from(3i64)).unwrap(), 3);
+        assert_eq!(f64::try_from(IValue::from(0.5)).unwrap(), 0.5);
+        assert!(bool::try_from(IValue::from(true)).unwrap());
+        assert_eq!(
+            Vec::<i64>::try_from(IValue::from(vec![1i64, 2])).unwrap(),
+            [1, 2]
+        );
+        assert_eq!(
+            Vec::<bool>::try_from(IValue::from(vec![true, false])).unwrap(),
+            [true, false]
+        );
+        assert_eq!(String::try_from(IValue::from("text")).unwrap(), "text");
+        assert!(matches!(IValue::from(()), IValue::None));
+
+        // no implicit conversions between variants
+        assert!(i64::try_from(IValue::from(1.0)).is_err());
+        assert!(f64::try_from(IValue::from(1i64)).is_err());
+        assert!(String::try_from(IValue::None).is_err());
+    }
+}
@@ -0,0 +1,92 @@
+use anyhow::{bail, Result};
+use std::os::raw::c_int;
+
+/// The element type of a tensor, in the order of `c10::ScalarType`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Kind {
+    Uint8,
+    Int8,
+    Int16,
+    Int,
+    Int64,
+    Half,
+    Float,
+    Double,
+    ComplexHalf,
+    ComplexFloat,
+    ComplexDouble,
+    Bool,
+    QInt8,
+    QUInt8,
+    QInt32,
+    BFloat16,
+}
+
+impl Kind {
+    const ALL: [Self; 16] = [
+        Self::Uint8,
+        Self::Int8,
+        Self::Int16,
+        Self::Int,
+        Self::Int64,
+        Self::Half,
+        Self::Float,
+        Self::Double,
+        Self::ComplexHalf,
+        Self::ComplexFloat,
+        Self::ComplexDouble,
+        Self::Bool,
+        Self::QInt8,
+        Self::QUInt8,
+        Self::QInt32,
+        Self::BFloat16,
+    ];
+
+    pub(super) fn to_c_int(self) -> c_int {
+        self as c_int
+    }
+
+    pub(super) fn of_c_int(kind: c_int) -> Result<Self> {
+        match usize::try_from(kind)
+            .ok()
+            .and_then(|kind| Self::ALL.get(kind))
+        {
+            Some(kind) => Ok(*kind),
+            None => bail!("unsupported scalar type {kind}"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Kind;
+
+    #[test]
+    fn kind_matches_scalar_type() {
+        // the values of c10::ScalarType
+        let scalar_types = [
+            (0, Kind::Uint8),
+            (1, Kind::Int8),
+            (2, Kind::Int16),
+            (3, Kind::Int),
+            (4, Kind::Int64),
+            (5, Kind::Half),
+            (6, Kind::Float),
+            (7, Kind::Double),
+            (8, Kind::ComplexHalf),
+            (9, Kind::ComplexFloat),
+            (10, Kind::ComplexDouble),
+            (11, Kind::Bool),
+            (12, Kind::QInt8),
+            (13, Kind::QUInt8),
+            (14, Kind::QInt32),
+            (15, Kind::BFloat16),
+        ];
+        for (value, kind) in scalar_types {
+            assert_eq!(Kind::of_c_int(value).unwrap(), kind);
+            assert_eq!(kind.to_c_int(), value);
+        }
+        assert!(Kind::of_c_int(-1).is_err());
+        assert!(Kind::of_c_int(16).is_err());
+    }
+}
@@ -0,0 +1,188 @@
+use super::{
+    ffi::{self, check, last_err, CModule as CPtr, CTensor},
+    ivalue::RawIValue,
+    Device, IValue, Kind, Tensor,
+};
+use anyhow::{anyhow, Result};
+use std::{
+    borrow::Borrow,
+    ffi::{CStr, CString},
+    os::raw::{c_char, c_int, c_void},
+    path::Path,
+};
+
+/// An owned TorchScript `torch::jit::script::Module`.
+#[derive(Debug)]
+pub struct CModule {
+    ptr: CPtr,
+}
+
+unsafe impl Send for CModule {}
+
+impl CModule {
+    /// Load a TorchScript module from a file.
+    pub fn load<P>(path: P) -> Result<Self>
+    where
+        P: AsRef<Path>,
+    {
+        let path = path_to_cstring(path.as_ref())?;
+        let ptr = check(unsafe { ffi::atm_load(path.as_ptr() as *mut c_char) })?;
+        Ok(Self { ptr })
+    }
+
+    /// Load a TorchScript module from a file onto `device`.
+    pub fn load_on_device<P>(path: P, device: Device) -> Result<Self>
+    where
+        P: AsRef<Path>,
+    {
+        let path = path_to_cstring(path.as_ref())?;
+        let ptr = check(unsafe {
+            ffi::atm_load_on_device(path.as_ptr() as *mut c_char, device.to_c_int())
+        })?;
+        Ok(Self { ptr })
+    }
+
+    /// Load a TorchScript module from serialized data.
+    pub fn load_data(data: &[u8]) -> Result<Self> {
+        let ptr = check(unsafe { ffi::atm_load_str(data.as_ptr() as *mut c_char, data.len()) })?;
+        Ok(Self { ptr })
+    }
+
+    /// Load a TorchScript module from serialized data onto `device`.
+    pub fn load_data_on_device(data: &[u8], device: Device) -> Result<Self> {
+        let ptr = check(unsafe {
+            ffi::atm_load_str_on_device(data.as_ptr() as *mut c_char, data.len(), device.to_c_int())
+        })?;
+        Ok(Self { ptr })
+    }
+
+    /// Run the `forward` method on tensor inputs.
+    pub fn forward_ts<T>(&self, inputs: &[T]) -> Result<Tensor>
+    where
+        T: Borrow<Tensor>,
+    {
+        let ptrs = tensor_ptrs(inputs);
+        let ptr = check(unsafe { ffi::atm_forward(self.ptr, ptrs.as_ptr(), len(&ptrs)?) })?;
+        Ok(unsafe { Tensor::from_raw(ptr) })
+    }
+
+    /// Run the `forward` method on generic inputs.
+    pub fn forward_is<T>(&self, inputs: &[T]) -> Result<IValue>
+    where
+        T: Borrow<IValue>,
+    {
+        let raws = ivalue_raws(inputs)?;
+        let ptrs: Vec<_> = raws.iter().map(RawIValue::as_ptr).collect();
+        let output =
+            RawIValue::new(unsafe { ffi::atm_forward_(self.ptr, ptrs.as_ptr(), len(&ptrs)?) })?;
+        IValue::from_raw(&output)
+    }
+
+    /// Run the method `name` on tensor inputs.
+    pub fn method_ts<T>(&self, name: &str, inputs: &[T]) -> Result<Tensor>
+    where
+        T: Borrow<Tensor>,
+    {
+        let name = CString::new(name)?;
+        let ptrs = tensor_ptrs(inputs);
+        let ptr = check(unsafe {
+            ffi::atm_method(
+                self.ptr,
+                name.as_ptr() as *mut c_char,
+                ptrs.as_ptr(),
+                len(&ptrs)?,
+            )
+        })?;
+        Ok(unsafe { Tensor::from_raw(ptr) })
+    }
+
+    /// Run the method `name` on generic inputs.
+    pub fn method_is<T>(&self, name: &str, inputs: &[T]) -> Result<IValue>
+    where
+        T: Borrow<IValue>,
+    {
+        let name = CString::new(name)?;
+        let raws = ivalue_raws(inputs)?;
+        let ptrs: Vec<_> = raws.iter().map(RawIValue::as_ptr).collect();
+        let output = RawIValue::new(unsafe {
+            ffi::atm_method_(
+                self.ptr,
+                name.as_ptr() as *mut c_char,
+                ptrs.as_ptr(),
+                len(&ptrs)?,
+            )
+        })?;
+        IValue::from_raw(&output)
+    }
+
+    pub fn eval(&mut self) -> Result<()> {
+        unsafe { ffi::atm_eval(self.ptr) };
+        last_err()
+    }
+
+    pub fn train(&mut self) -> Result<()> {
+        unsafe { ffi::atm_train(self.ptr) };
+        last_err()
+    }
+
+    /// Move the parameters to `device` and convert them to `kind`.
+    pub fn to(&mut self, device: Device, kind: Kind, non_blocking: bool) -> Result<()> {
+        unsafe { ffi::atm_to(self.ptr, device.to_c_int(), kind.to_c_int(), non_blocking) };
+        last_err()
+    }
+
+    pub fn save<P>(&self, path: P) -> Result<()>
+    where
+        P: AsRef<Path>,
+    {
+        let path = path_to_cstring(path.as_ref())?;
+        unsafe { ffi::atm_save(self.ptr, path.as_ptr() as *mut c_char) };
+        last_err()
+    }
+
+    pub fn named_parameters(&self) -> Result<Vec<(String, Tensor)>> {
+        extern "C" fn add(data: *mut c_void, name: *mut c_char, tensor: CTensor) {
+            let params = unsafe { &mut *(data as *mut Vec<(String, Tensor)>) };
+            let name = unsafe { CStr::from_ptr(name) }
+                .to_string_lossy()
+                .into_owned();
+            params.push((name, unsafe { Tensor::from_raw(tensor) }));
+        }
+
+        let mut params: Vec<(String, Tensor)> = vec![];
+        unsafe { ffi::atm_named_parameters(self.ptr, &mut params as *mut _ as *mut c_void, add) };
+        last_err()?;
+        Ok(params)
+    }
+}
+
+impl Drop for CModule {
+    fn drop(&mut self) {
+        unsafe { ffi::atm_free(self.ptr) }
+    }
+}
+
+fn path_to_cstring(path: &Path) -> Result<CString> {
+    let path = path
+        .to_str()
+        .ok_or_else(|| anyhow!("non-UTF-8 path {}", path.display()))?;
+    Ok(CString::new(path)?)
+}
+
+fn tensor_ptrs<T>(inputs: &[T]) -> Vec<CTensor>
+where
+    T: Borrow<Tensor>,
+{
+    inputs.iter().map(|t| t.borrow().as_ptr()).collect()
+}
+
+fn ivalue_raws<T>(inputs: &[T]) -> Result<Vec<RawIValue>>
+where
+    T: Borrow<IValue>,
+{
+    inputs.iter().map(|v| v.borrow().to_raw()).collect()
+}
+
+fn len<T>(values: &[T]) -> Result<c_int> {
+    c_int::try_from(values.len()).map_err(|_| anyhow!("too many inputs"))
+}
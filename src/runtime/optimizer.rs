@@ -0,0 +1,117 @@
+use super::{
+    ffi::{self, check, last_err, COptimizer as CPtr},
+    Tensor,
+};
+use anyhow::Result;
+
+/// An owned `torch::optim::Optimizer`.
+#[derive(Debug)]
+pub struct COptimizer {
+    ptr: CPtr,
+}
+
+unsafe impl Send for COptimizer {}
+
+impl COptimizer {
+    pub fn adam(learning_rate: f64, beta1: f64, beta2: f64, weight_decay: f64) -> Result<Self> {
+        let ptr = check(unsafe { ffi::ato_adam(learning_rate, beta1, beta2, weight_decay) })?;
+        Ok(Self { ptr })
+    }
+
+    pub fn adamw(learning_rate: f64, beta1: f64, beta2: f64, weight_decay: f64) -> Result<Self> {
+        let ptr = check(unsafe { ffi::ato_adamw(learning_rate, beta1, beta2, weight_decay) })?;
+        Ok(Self { ptr })
+    }
+
+    pub fn rms_prop(
+        learning_rate: f64,
+        alpha: f64,
+        eps: f64,
+        weight_decay: f64,
+        momentum: f64,
+        centered: bool,
+    ) -> Result<Self> {
+        let ptr = check(unsafe {
+            ffi::ato_rms_prop(
+                learning_rate,
+                alpha,
+                eps,
+                weight_decay,
+                momentum,
+                centered as _,
+            )
+        })?;
+        Ok(Self { ptr })
+    }
+
+    pub fn sgd(
+        learning_rate: f64,
+        momentum: f64,
+        dampening: f64,
+        weight_decay: f64,
+        nesterov: bool,
+    ) -> Result<Self> {
+        let ptr = check(unsafe {
+            ffi::ato_sgd(
+                learning_rate,
+                momentum,
+                dampening,
+                weight_decay,
+                nesterov as _,
+            )
+        })?;
+        Ok(Self { ptr })
+    }
+
+    /// Add a parameter to optimize to the parameter group `group`.
+    pub fn add_parameters(&mut self, tensor: &Tensor, group: usize) -> Result<()> {
+        unsafe { ffi::ato_add_parameters(self.ptr, tensor.as_ptr(), group) };
+        last_err()
+    }
+
+    pub fn set_learning_rate(&mut self, learning_rate: f64) -> Result<()> {
+        unsafe { ffi::ato_set_learning_rate(self.ptr, learning_rate) };
+        last_err()
+    }
+
+    pub fn set_learning_rate_group(&mut self, group: usize, learning_rate: f64) -> Result<()> {
+        unsafe { ffi::ato_set_learning_rate_group(self.ptr, group, learning_rate) };
+        last_err()
+    }
+
+    pub fn set_momentum(&mut self, momentum: f64) -> Result<()> {
+        unsafe { ffi::ato_set_momentum(self.ptr, momentum) };
+        last_err()
+    }
+
+    pub fn set_momentum_group(&mut self, group: usize, momentum: f64) -> Result<()> {
+        unsafe { ffi::ato_set_momentum_group(self.ptr, group, momentum) };
+        last_err()
+    }
+
+    pub fn set_weight_decay(&mut self, weight_decay: f64) -> Result<()> {
+        unsafe { ffi::ato_set_weight_decay(self.ptr, weight_decay) };
+        last_err()
+    }
+
+    pub fn set_weight_decay_group(&mut self, group: usize, weight_decay: f64) -> Result<()> {
+        unsafe { ffi::ato_set_weight_decay_group(self.ptr, group, weight_decay) };
+        last_err()
+    }
+
+    pub fn zero_grad(&mut self) -> Result<()> {
+        unsafe { ffi::ato_zero_grad(self.ptr) };
+        last_err()
+    }
+
+    pub fn step(&mut self) -> Result<()> {
+        unsafe { ffi::ato_step(self.ptr) };
+        last_err()
+    }
+}
+
+impl Drop for COptimizer {
+    fn drop(&mut self) {
+        unsafe { ffi::ato_free(self.ptr) }
+    }
+}
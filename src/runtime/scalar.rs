@@ -0,0 +1,67 @@
+use super::ffi::{self, check, take_string, CScalar};
+use anyhow::Result;
+use std::{fmt, mem, os::raw::c_void};
+
+/// An owned `torch::Scalar`.
+#[derive(Debug)]
+pub struct Scalar {
+    ptr: CScalar,
+}
+
+unsafe impl Send for Scalar {}
+
+impl Scalar {
+    pub fn int(value: i64) -> Result<Self> {
+        let ptr = check(unsafe { ffi::ats_int(value) })?;
+        Ok(Self { ptr })
+    }
+
+    pub fn float(value: f64) -> Result<Self> {
+        let ptr = check(unsafe { ffi::ats_float(value) })?;
+        Ok(Self { ptr })
+    }
+
+    /// Take the ownership of a `scalar` handle.
+    ///
+    /// # Safety
+    /// `ptr` must be a valid handle that is not owned elsewhere.
+    pub unsafe fn from_raw(ptr: *mut c_void) -> Self {
+        Self { ptr }
+    }
+
+    /// Release the ownership of the handle.
+    pub fn into_raw(self) -> *mut c_void {
+        let ptr = self.ptr;
+        mem::forget(self);
+        ptr
+    }
+
+    /// Borrow the handle to pass it to libtch functions.
+    pub fn as_ptr(&self) -> *mut c_void {
+        self.ptr
+    }
+
+    pub fn to_int(&self) -> Result<i64> {
+        check(unsafe { ffi::ats_to_int(self.ptr) })
+    }
+
+    pub fn to_float(&self) -> Result<f64> {
+        check(unsafe { ffi::ats_to_float(self.ptr) })
+    }
+}
+
+impl fmt::Display for Scalar {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let ptr = unsafe { ffi::ats_to_string(self.ptr) };
+        match check(ptr) {
+            Ok(ptr) if !ptr.is_null() => f.write_str(&unsafe { take_string(ptr) }),
+            _ => Err(fmt::Error),
+        }
+    }
+}
+
+impl Drop for Scalar {
+    fn drop(&mut self) {
+        unsafe { ffi::ats_free(self.ptr) }
+    }
+}
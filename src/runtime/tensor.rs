@@ -0,0 +1,200 @@
+use super::{
+    ffi::{self, check, last_err, take_string, CTensor},
+    Device, Kind,
+};
+use anyhow::{ensure, Result};
+use std::{fmt, mem, os::raw::c_void};
+
+/// Rust types that can be copied to and from tensors.
+///
+/// # Safety
+/// The size and layout of the type must match the element of
+/// [KIND](Element::KIND).
+pub unsafe trait Element: Copy + Default {
+    const KIND: Kind;
+}
+
+unsafe impl Element for u8 {
+    const KIND: Kind = Kind::Uint8;
+}
+
+unsafe impl Element for i8 {
+    const KIND: Kind = Kind::Int8;
+}
+
+unsafe impl Element for i16 {
+    const KIND: Kind = Kind::Int16;
+}
+
+unsafe impl Element for i32 {
+    const KIND: Kind = Kind::Int;
+}
+
+unsafe impl Element for i64 {
+    const KIND: Kind = Kind::Int64;
+}
+
+unsafe impl Element for f32 {
+    const KIND: Kind = Kind::Float;
+}
+
+unsafe impl Element for f64 {
+    const KIND: Kind = Kind::Double;
+}
+
+unsafe impl Element for bool {
+    const KIND: Kind = Kind::Bool;
+}
+
+/// An owned `torch::Tensor`.
+#[derive(Debug)]
+pub struct Tensor {
+    ptr: CTensor,
+}
+
+unsafe impl Send for Tensor {}
+
+impl Tensor {
+    /// Create an undefined tensor.
+    pub fn new() -> Result<Self> {
+        let ptr = check(unsafe { ffi::at_new_tensor() })?;
+        Ok(Self { ptr })
+    }
+
+    /// Create a CPU tensor with the given shape by copying `data`.
+    pub fn from_data<T>(data: &[T], shape: &[i64]) -> Result<Self>
+    where
+        T: Element,
+    {
+        let numel: i64 = shape.iter().product();
+        ensure!(
+            usize::try_from(numel).ok() == Some(data.len()),
+            "shape {shape:?} does not match {} elements",
+            data.len()
+        );
+
+        let ptr = check(unsafe {
+            ffi::at_tensor_of_data(
+                data.as_ptr() as *const c_void,
+                shape.as_ptr(),
+                shape.len(),
+                mem::size_of::<T>(),
+                T::KIND.to_c_int(),
+            )
+        })?;
+        Ok(Self { ptr })
+    }
+
+    /// Create a 1-D CPU tensor by copying `data`.
+    pub fn from_slice<T>(data: &[T]) -> Result<Self>
+    where
+        T: Element,
+    {
+        Self::from_data(data, &[data.len() as i64])
+    }
+
+    /// Take the ownership of a `tensor` handle, for example one
+    /// returned by a generated `atg_*` function.
+    ///
+    /// # Safety
+    /// `ptr` must be a valid handle that is not owned elsewhere.
+    pub unsafe fn from_raw(ptr: *mut c_void) -> Self {
+        Self { ptr }
+    }
+
+    /// Release the ownership of the handle.
+    pub fn into_raw(self) -> *mut c_void {
+        let ptr = self.ptr;
+        mem::forget(self);
+        ptr
+    }
+
+    /// Borrow the handle to pass it to libtch functions.
+    pub fn as_ptr(&self) -> *mut c_void {
+        self.ptr
+    }
+
+    /// Create a new tensor sharing the same storage.
+    pub fn shallow_clone(&self) -> Result<Self> {
+        let ptr = check(unsafe { ffi::at_shallow_clone(self.ptr) })?;
+        Ok(Self { ptr })
+    }
+
+    pub fn defined(&self) -> Result<bool> {
+        Ok(check(unsafe { ffi::at_defined(self.ptr) })? != 0)
+    }
+
+    pub fn dim(&self) -> Result<usize> {
+        check(unsafe { ffi::at_dim(self.ptr) })
+    }
+
+    pub fn size(&self) -> Result<Vec<i64>> {
+        let mut dims = vec![0; self.dim()?];
+        unsafe { ffi::at_shape(self.ptr, dims.as_mut_ptr()) };
+        last_err()?;
+        Ok(dims)
+    }
+
+    pub fn numel(&self) -> Result<usize> {
+        Ok(self.size()?.iter().product::<i64>() as usize)
+    }
+
+    pub fn kind(&self) -> Result<Kind> {
+        Kind::of_c_int(check(unsafe { ffi::at_scalar_type(self.ptr) })?)
+    }
+
+    pub fn device(&self) -> Result<Device> {
+        Device::of_c_int(check(unsafe { ffi::at_device(self.ptr) })?)
+    }
+
+    pub fn requires_grad(&self) -> Result<bool> {
+        Ok(check(unsafe { ffi::at_requires_grad(self.ptr) })? != 0)
+    }
+
+    pub fn backward(&self) -> Result<()> {
+        unsafe { ffi::at_backward(self.ptr, 0, 0) };
+        last_err()
+    }
+
+    /// Copy the elements to a [Vec]. The tensor is moved to CPU and
+    /// made contiguous if needed. `T` must match the tensor kind.
+    pub fn to_vec<T>(&self) -> Result<Vec<T>>
+    where
+        T: Element,
+    {
+        let kind = self.kind()?;
+        ensure!(
+            kind == T::KIND,
+            "cannot copy a {kind:?} tensor to {:?} elements",
+            T::KIND
+        );
+
+        let mut data = vec![T::default(); self.numel()?];
+        unsafe {
+            ffi::at_copy_data(
+                self.ptr,
+                data.as_mut_ptr() as *mut c_void,
+                data.len(),
+                mem::size_of::<T>(),
+            )
+        };
+        last_err()?;
+        Ok(data)
+    }
+}
+
+impl fmt::Display for Tensor {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let ptr = unsafe { ffi::at_to_string(self.ptr, 80) };
+        match check(ptr) {
+            Ok(ptr) if !ptr.is_null() => f.write_str(&unsafe { take_string(ptr) }),
+            _ => Err(fmt::Error),
+        }
+    }
+}
+
+impl Drop for Tensor {
+    fn drop(&mut self) {
+        unsafe { ffi::at_free(self.ptr) }
+    }
+}